@@ -9,13 +9,20 @@
 #![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
 #![doc = include_str!("../README.md")]
 
-#[cfg(not(slabbable_hasmap = "_somethingelse"))]
-use hashbrown::HashMap as SelectedHashMap;
+use core::hash::BuildHasher;
 
-#[cfg(not(slabbable_hasher = "_somethingelse"))]
-use nohash_hasher::BuildNoHashHasher as SelectedHasher;
+use nohash_hasher::BuildNoHashHasher;
 
-use slabbable::{ReservedSlot, Slabbable, SlabbableError};
+#[cfg(feature = "stats")]
+use slabbable::SlabStats;
+use slabbable::{ReservedSlot, ResizeMode, SlabMemory, Slabbable, SlabbableError};
+
+mod map;
+#[cfg(feature = "std")]
+#[doc(inline)]
+pub use map::StdHashMap;
+#[doc(inline)]
+pub use map::{HashBrown, MapBackend, SlabMap};
 
 #[derive(Debug)]
 enum ReserveStatus<Item> {
@@ -24,9 +31,11 @@ enum ReserveStatus<Item> {
 }
 
 /// Holder
-#[derive(Debug)]
-pub struct HashSlab<Item> {
-    inner: SelectedHashMap<usize, ReserveStatus<Item>, SelectedHasher<usize>>,
+///
+/// The hasher S and the map backend M can be swapped, e.g.
+/// `HashSlab<Item, std::hash::RandomState, StdHashMap>`.
+pub struct HashSlab<Item, S: BuildHasher = BuildNoHashHasher<usize>, M: MapBackend = HashBrown> {
+    inner: M::Map<ReserveStatus<Item>, S>,
     // HashBrown seems to report wrong capacity() for guaranteed no-realloc
     // so we have to track our own to ensure it doesn't move things around.
     max_capacity: usize,
@@ -34,17 +43,40 @@ pub struct HashSlab<Item> {
     cur: usize,
     // wraps
     rev: usize,
+    // highest ID issued before cur wraps
+    last_id: usize,
+    #[cfg(feature = "stats")]
+    stats: SlabStats,
+}
+
+impl<Item, S, M> core::fmt::Debug for HashSlab<Item, S, M>
+where
+    S: BuildHasher,
+    M: MapBackend,
+    M::Map<ReserveStatus<Item>, S>: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("HashSlab")
+            .field("inner", &self.inner)
+            .field("max_capacity", &self.max_capacity)
+            .field("cur", &self.cur)
+            .field("rev", &self.rev)
+            .field("last_id", &self.last_id)
+            .finish()
+    }
 }
 
-impl<Item> HashSlab<Item> {
+impl<Item, S: BuildHasher, M: MapBackend> HashSlab<Item, S, M> {
     fn _take_next_cur(&mut self) -> usize {
         let spot = self.cur;
-        if self.cur == usize::MAX {
+        if self.cur >= self.last_id {
             self.cur = 0;
             self.rev = match self.rev {
                 usize::MAX => 0,
                 _ => self.rev + 1,
             };
+            #[cfg(feature = "stats")]
+            self.stats.on_revolution();
         } else {
             self.cur += 1;
         }
@@ -52,54 +84,81 @@ impl<Item> HashSlab<Item> {
     }
 }
 
-impl<Item> Slabbable<HashSlab<Item>, Item> for HashSlab<Item>
+impl<Item, S, M> Slabbable<HashSlab<Item, S, M>, Item> for HashSlab<Item, S, M>
 where
     Item: core::fmt::Debug + Clone,
+    S: BuildHasher + Default,
+    M: MapBackend,
 {
     type Error = SlabbableError;
     /// See trait
     fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
-        let inner: SelectedHashMap<usize, ReserveStatus<Item>, SelectedHasher<usize>> =
-            SelectedHashMap::<usize, ReserveStatus<Item>, SelectedHasher<usize>>::with_capacity_and_hasher(
-                cap,
-                SelectedHasher::default(),
-            );
+        let inner = M::Map::<ReserveStatus<Item>, S>::with_capacity_and_hasher(cap, S::default());
         Ok(Self {
             inner,
             max_capacity: cap,
             cur: 0,
             rev: 0,
+            last_id: usize::MAX,
+            #[cfg(feature = "stats")]
+            stats: SlabStats::default(),
         })
     }
     #[inline]
     fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
         // Slab re-allocators upon grow - we want stable addresses
         if self.max_capacity < self.inner.len() + 1 {
+            #[cfg(feature = "stats")]
+            self.stats.on_at_capacity();
             return Err(SlabbableError::AtCapacity(self.max_capacity));
         }
-        let slot = self._take_next_cur();
-        // TOOD: std hashmap try_insert is experimental
-        match self.inner.try_insert(slot, ReserveStatus::Reserved) {
-            Ok(_) => Ok(ReservedSlot::issue(slot)),
-            _ => Err(SlabbableError::Bug(
-                "Next entry by _take_next_cur() already occupied.",
-            )),
+        // After cur wraps the next ID may still be held by a long-lived slot.
+        // At most len() IDs can be live so within len() + 1 attempts one is free.
+        for _attempt in 0..=self.inner.len() {
+            let slot = self._take_next_cur();
+            // TOOD: std hashmap try_insert is experimental
+            match self.inner.try_insert(slot, ReserveStatus::Reserved) {
+                Ok(_) => {
+                    #[cfg(feature = "stats")]
+                    self.stats.on_reserve();
+                    return Ok(ReservedSlot::issue(slot));
+                }
+                _ => continue,
+            }
         }
+        Err(SlabbableError::Bug(
+            "No free entry found by _take_next_cur() within capacity.",
+        ))
     }
     #[inline]
     fn take_reserved_with(&mut self, slot: ReservedSlot, with: Item) -> Result<usize, Self::Error> {
         let id = slot.id();
-
-        match self.inner.insert(id, ReserveStatus::Taken(with)) {
-            Some(v) => match v {
-                ReserveStatus::Reserved => Ok(id),
-                _ => Err(SlabbableError::Bug("Key was already occupied.")),
-            },
+        match self.inner.get_mut(id) {
+            Some(status @ ReserveStatus::Reserved) => {
+                *status = ReserveStatus::Taken(with);
+                #[cfg(feature = "stats")]
+                self.stats.on_take_reserved();
+                Ok(id)
+            }
+            Some(ReserveStatus::Taken(_)) => Err(SlabbableError::Bug("Key was already occupied.")),
             None => Err(SlabbableError::Bug("Key was not reserved correctly.")),
         }
     }
     /// See trait
     #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        let id = slot.id();
+        match self.inner.get(id) {
+            Some(ReserveStatus::Reserved) => {}
+            _ => return Err(SlabbableError::InvalidIndex(id)),
+        }
+        self.inner.remove(id);
+        #[cfg(feature = "stats")]
+        self.stats.on_cancel_reserved();
+        Ok(())
+    }
+    /// See trait
+    #[inline]
     fn take_next_with(&mut self, with: Item) -> Result<usize, Self::Error> {
         let reserved_slot = self.reserve_next()?;
         self.take_reserved_with(reserved_slot, with)
@@ -107,15 +166,24 @@ where
     /// See trait
     #[inline]
     fn mark_for_reuse(&mut self, slot: usize) -> Result<Item, Self::Error> {
-        match self.inner.remove(&slot) {
-            Some(ReserveStatus::Taken(i)) => Ok(i),
-            _ => Err(SlabbableError::InvalidIndex(slot)),
+        // Reserved slot is not ours to free
+        match self.inner.get(slot) {
+            Some(ReserveStatus::Taken(_)) => {}
+            _ => return Err(SlabbableError::InvalidIndex(slot)),
+        }
+        match self.inner.remove(slot) {
+            Some(ReserveStatus::Taken(i)) => {
+                #[cfg(feature = "stats")]
+                self.stats.on_free();
+                Ok(i)
+            }
+            _ => Err(SlabbableError::Bug("Taken slot vanished upon remove")),
         }
     }
     /// See trait
     #[inline]
     fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut Item>, Self::Error> {
-        match self.inner.get_mut(&slot) {
+        match self.inner.get_mut(slot) {
             Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
             _ => Err(SlabbableError::InvalidIndex(slot)),
         }
@@ -123,7 +191,7 @@ where
     /// See trait
     #[inline]
     fn slot_get_ref(&self, slot: usize) -> Result<Option<&Item>, Self::Error> {
-        match self.inner.get(&slot) {
+        match self.inner.get(slot) {
             Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
             _ => Err(SlabbableError::InvalidIndex(slot)),
         }
@@ -142,9 +210,259 @@ where
             1_usize.. => Some(rem),
         }
     }
+    /// See trait - the sequential IDs wrap around below the limit.
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        let Some(last_id) = limit.checked_sub(1) else {
+            return false;
+        };
+        self.last_id = last_id;
+        if self.cur > last_id {
+            self.cur = last_id;
+            self._take_next_cur();
+        }
+        true
+    }
+    /// See trait - the map is rebuilt for the new capacity moving the entries.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        let held = self.inner.len();
+        if held > 0 && mode == ResizeMode::IfEmpty {
+            return Err(SlabbableError::NotEmpty(held));
+        }
+        if held > new_cap {
+            return Err(SlabbableError::BelowOccupied(held));
+        }
+        let mut inner =
+            M::Map::<ReserveStatus<Item>, S>::with_capacity_and_hasher(new_cap, S::default());
+        self.inner.drain_into(&mut inner);
+        self.inner = inner;
+        self.max_capacity = new_cap;
+        Ok(())
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        SlabMemory::from_slots(
+            self.inner.allocation_size(),
+            self.inner.capacity(),
+            self.inner.len(),
+            size_of::<Item>(),
+        )
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        Some(self.stats)
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
     /// See trait
     fn reap(&mut self) -> Option<usize> {
         // We don't support it
         None
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wrap_skips_live_id() {
+        let mut slab = HashSlab::<u8>::with_fixed_capacity(3).unwrap();
+        // long-lived listener holds ID 0 across the wrap
+        assert_eq!(slab.take_next_with(0).unwrap(), 0);
+        slab.cur = usize::MAX - 1;
+
+        let near_max = slab.take_next_with(1).unwrap();
+        assert_eq!(near_max, usize::MAX - 1);
+        assert_eq!(slab.mark_for_reuse(near_max).unwrap(), 1);
+
+        let at_max = slab.take_next_with(2).unwrap();
+        assert_eq!(at_max, usize::MAX);
+        assert_eq!(slab.rev, 1);
+
+        assert_eq!(slab.take_next_with(3).unwrap(), 1);
+        assert_eq!(slab.slot_get_ref(0).unwrap(), Some(&0));
+    }
+
+    #[test]
+    fn wrap_skips_consecutive_live_ids() {
+        let mut slab = HashSlab::<u8>::with_fixed_capacity(4).unwrap();
+        assert_eq!(slab.take_next_with(0).unwrap(), 0);
+        assert_eq!(slab.take_next_with(1).unwrap(), 1);
+        assert_eq!(slab.take_next_with(2).unwrap(), 2);
+        slab.cur = usize::MAX;
+
+        assert_eq!(slab.take_next_with(3).unwrap(), usize::MAX);
+        assert_eq!(slab.mark_for_reuse(usize::MAX).unwrap(), 3);
+
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(reserved.id(), 3);
+        assert_eq!(slab.take_reserved_with(reserved, 4).unwrap(), 3);
+    }
+
+    #[test]
+    fn wrap_below_key_limit() {
+        let mut slab = HashSlab::<u8>::with_fixed_capacity(3).unwrap();
+        assert!(!slab.set_key_limit(0));
+        assert_eq!(slab.take_next_with(0).unwrap(), 0);
+        slab.cur = 5;
+
+        // cur past the limit wraps straight away skipping the live ID
+        assert!(slab.set_key_limit(3));
+        assert_eq!(slab.rev, 1);
+        assert_eq!(slab.take_next_with(1).unwrap(), 1);
+        assert_eq!(slab.take_next_with(2).unwrap(), 2);
+        assert_eq!(slab.mark_for_reuse(1).unwrap(), 1);
+        assert_eq!(slab.take_next_with(3).unwrap(), 1);
+    }
+
+    #[test]
+    fn wrap_at_capacity() {
+        let mut slab = HashSlab::<u8>::with_fixed_capacity(2).unwrap();
+        assert_eq!(slab.take_next_with(0).unwrap(), 0);
+        assert_eq!(slab.take_next_with(1).unwrap(), 1);
+        slab.cur = usize::MAX;
+
+        assert_eq!(slab.take_next_with(2), Err(SlabbableError::AtCapacity(2)));
+        assert_eq!(slab.cur, usize::MAX);
+    }
+
+    #[test]
+    fn resize_keeps_keys_and_ids() {
+        let mut slab = HashSlab::<u8>::with_fixed_capacity(2).unwrap();
+        let a = slab.take_next_with(1).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(
+            slab.try_resize_fixed_capacity(4, ResizeMode::IfEmpty),
+            Err(SlabbableError::NotEmpty(2))
+        );
+        assert_eq!(
+            slab.try_resize_fixed_capacity(1, ResizeMode::NoAddressesHeld),
+            Err(SlabbableError::BelowOccupied(2))
+        );
+        slab.try_resize_fixed_capacity(3, ResizeMode::NoAddressesHeld)
+            .unwrap();
+        assert_eq!(slab.capacity(), 3);
+        assert_eq!(slab.slot_get_ref(a).unwrap(), Some(&1));
+        let b = slab.take_reserved_with(reserved, 2).unwrap();
+        assert_eq!(slab.take_next_with(3).unwrap(), b + 1);
+        assert_eq!(slab.take_next_with(4), Err(SlabbableError::AtCapacity(3)));
+
+        let mut empty = HashSlab::<u8>::with_fixed_capacity(2).unwrap();
+        empty
+            .try_resize_fixed_capacity(0, ResizeMode::IfEmpty)
+            .unwrap();
+        assert_eq!(empty.remaining(), None);
+    }
+
+    #[test]
+    fn std_hashmap_backend() {
+        let mut slab =
+            HashSlab::<u8, std::hash::RandomState, StdHashMap>::with_fixed_capacity(2).unwrap();
+        let a = slab.take_next_with(1).unwrap();
+        let b = slab.take_next_with(2).unwrap();
+        assert_eq!(slab.take_next_with(3), Err(SlabbableError::AtCapacity(2)));
+        assert_eq!(slab.mark_for_reuse(a).unwrap(), 1);
+        assert_eq!(slab.slot_get_ref(b).unwrap(), Some(&2));
+    }
+
+    #[test]
+    fn foldhash_hasher() {
+        let mut slab =
+            HashSlab::<u8, hashbrown::DefaultHashBuilder>::with_fixed_capacity(2).unwrap();
+        let a = slab.take_next_with(1).unwrap();
+        *slab.slot_get_mut(a).unwrap().unwrap() = 5;
+        assert_eq!(slab.slot_get_ref(a).unwrap(), Some(&5));
+    }
+
+    #[test]
+    fn memory_usage() {
+        let mut slab = HashSlab::<u64>::with_fixed_capacity(100).unwrap();
+        let empty = slab.memory_usage();
+        assert!(empty.allocated_bytes >= 100 * size_of::<u64>());
+        assert!(empty.overhead_per_slot >= size_of::<usize>());
+        // amortized over the slots the map allocated, not the fixed capacity
+        assert_eq!(
+            empty.overhead_per_slot,
+            empty.allocated_bytes / slab.inner.capacity() - size_of::<u64>()
+        );
+        assert_eq!(empty.live_bytes, 0);
+        assert_eq!(empty.reclaimable_bytes, 0);
+
+        slab.take_next_with(1).unwrap();
+        slab.reserve_next().unwrap();
+        let two = slab.memory_usage();
+        assert_eq!(two.allocated_bytes, empty.allocated_bytes);
+        assert_eq!(
+            two.live_bytes,
+            2 * (size_of::<u64>() + two.overhead_per_slot)
+        );
+
+        let std_slab =
+            HashSlab::<u64, std::hash::RandomState, StdHashMap>::with_fixed_capacity(100).unwrap();
+        assert_eq!(
+            std_slab.memory_usage().allocated_bytes,
+            empty.allocated_bytes
+        );
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() {
+        let mut slab = HashSlab::<u8>::with_fixed_capacity(2).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(
+            slab.mark_for_reuse(reserved.id()),
+            Err(SlabbableError::InvalidIndex(reserved.id()))
+        );
+        let a = slab.take_next_with(1).unwrap();
+        assert_eq!(slab.take_next_with(2), Err(SlabbableError::AtCapacity(2)));
+        slab.take_reserved_with(reserved, 0).unwrap();
+        slab.mark_for_reuse(a).unwrap();
+        slab.cur = usize::MAX;
+        slab.take_next_with(3).unwrap();
+        slab.take_next_with(4).unwrap_err();
+
+        assert_eq!(
+            slab.stats(),
+            Some(SlabStats {
+                takes: 3,
+                frees: 1,
+                live: 2,
+                reserved: 0,
+                high_watermark: 2,
+                at_capacity: 2,
+                revolutions: 1,
+            })
+        );
+        slab.reset_stats();
+        assert_eq!(slab.stats().unwrap().takes, 0);
+        assert_eq!(slab.stats().unwrap().high_watermark, 2);
+    }
+
+    #[test]
+    fn cancel_reserved() {
+        let mut slab = HashSlab::<u8>::with_fixed_capacity(1).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        let id = reserved.id();
+        slab.cancel_reserved(reserved).unwrap();
+        assert_eq!(
+            slab.take_reserved_with(ReservedSlot::issue(id), 0),
+            Err(SlabbableError::Bug("Key was not reserved correctly."))
+        );
+        assert_eq!(slab.slot_get_ref(id), Err(SlabbableError::InvalidIndex(id)));
+        let taken = slab.take_next_with(1).unwrap();
+        assert_eq!(
+            slab.cancel_reserved(ReservedSlot::issue(taken)),
+            Err(SlabbableError::InvalidIndex(taken))
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! HashMap backends for HashSlab
+
+use core::hash::BuildHasher;
+
+/// Operations HashSlab needs from the underlying usize keyed map.
+pub trait SlabMap<V, S> {
+    /// Map with room for capacity entries using the given hasher
+    fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self;
+    /// Number of entries in the map
+    fn len(&self) -> usize;
+    /// Whether the map is empty
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Get reference of entry
+    fn get(&self, key: usize) -> Option<&V>;
+    /// Get mutable reference of entry
+    fn get_mut(&mut self, key: usize) -> Option<&mut V>;
+    /// Insert entry returning the previous one
+    fn insert(&mut self, key: usize, value: V) -> Option<V>;
+    /// Insert entry only if the key is vacant, otherwise hand the value back
+    fn try_insert(&mut self, key: usize, value: V) -> Result<(), V>;
+    /// Remove entry
+    fn remove(&mut self, key: usize) -> Option<V>;
+    /// Move all the entries into the other map
+    fn drain_into(&mut self, other: &mut Self);
+    /// Entries the map holds without reallocating
+    fn capacity(&self) -> usize;
+    /// Bytes allocated by the map for its table
+    fn allocation_size(&self) -> usize;
+}
+
+/// Selects the HashMap implementation backing HashSlab.
+pub trait MapBackend {
+    /// The map type storing values V hashed by S
+    type Map<V, S: BuildHasher>: SlabMap<V, S>;
+}
+
+/// hashbrown::HashMap backend (default)
+#[derive(Debug)]
+pub struct HashBrown;
+
+impl MapBackend for HashBrown {
+    type Map<V, S: BuildHasher> = hashbrown::HashMap<usize, V, S>;
+}
+
+impl<V, S: BuildHasher> SlabMap<V, S> for hashbrown::HashMap<usize, V, S> {
+    #[inline]
+    fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        hashbrown::HashMap::with_capacity_and_hasher(capacity, hasher)
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        hashbrown::HashMap::len(self)
+    }
+    #[inline]
+    fn get(&self, key: usize) -> Option<&V> {
+        hashbrown::HashMap::get(self, &key)
+    }
+    #[inline]
+    fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+        hashbrown::HashMap::get_mut(self, &key)
+    }
+    #[inline]
+    fn insert(&mut self, key: usize, value: V) -> Option<V> {
+        hashbrown::HashMap::insert(self, key, value)
+    }
+    #[inline]
+    fn try_insert(&mut self, key: usize, value: V) -> Result<(), V> {
+        match self.entry(key) {
+            hashbrown::hash_map::Entry::Vacant(v) => {
+                v.insert(value);
+                Ok(())
+            }
+            hashbrown::hash_map::Entry::Occupied(_) => Err(value),
+        }
+    }
+    #[inline]
+    fn remove(&mut self, key: usize) -> Option<V> {
+        hashbrown::HashMap::remove(self, &key)
+    }
+    #[inline]
+    fn drain_into(&mut self, other: &mut Self) {
+        other.extend(self.drain());
+    }
+    #[inline]
+    fn capacity(&self) -> usize {
+        hashbrown::HashMap::capacity(self)
+    }
+    #[inline]
+    fn allocation_size(&self) -> usize {
+        hashbrown::HashMap::allocation_size(self)
+    }
+}
+
+/// std::collections::HashMap backend
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct StdHashMap;
+
+#[cfg(feature = "std")]
+impl MapBackend for StdHashMap {
+    type Map<V, S: BuildHasher> = std::collections::HashMap<usize, V, S>;
+}
+
+#[cfg(feature = "std")]
+impl<V, S: BuildHasher> SlabMap<V, S> for std::collections::HashMap<usize, V, S> {
+    #[inline]
+    fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        std::collections::HashMap::with_capacity_and_hasher(capacity, hasher)
+    }
+    #[inline]
+    fn len(&self) -> usize {
+        std::collections::HashMap::len(self)
+    }
+    #[inline]
+    fn get(&self, key: usize) -> Option<&V> {
+        std::collections::HashMap::get(self, &key)
+    }
+    #[inline]
+    fn get_mut(&mut self, key: usize) -> Option<&mut V> {
+        std::collections::HashMap::get_mut(self, &key)
+    }
+    #[inline]
+    fn insert(&mut self, key: usize, value: V) -> Option<V> {
+        std::collections::HashMap::insert(self, key, value)
+    }
+    #[inline]
+    fn try_insert(&mut self, key: usize, value: V) -> Result<(), V> {
+        // std try_insert is experimental
+        match self.entry(key) {
+            std::collections::hash_map::Entry::Vacant(v) => {
+                v.insert(value);
+                Ok(())
+            }
+            std::collections::hash_map::Entry::Occupied(_) => Err(value),
+        }
+    }
+    #[inline]
+    fn remove(&mut self, key: usize) -> Option<V> {
+        std::collections::HashMap::remove(self, &key)
+    }
+    #[inline]
+    fn drain_into(&mut self, other: &mut Self) {
+        other.extend(self.drain());
+    }
+    #[inline]
+    fn capacity(&self) -> usize {
+        std::collections::HashMap::capacity(self)
+    }
+    fn allocation_size(&self) -> usize {
+        // std does not tell so estimate as per the SwissTable layout it uses:
+        // buckets of (K, V) followed by a control byte per bucket and a group.
+        const GROUP_WIDTH: usize = 16;
+        let cap = self.capacity();
+        if cap == 0 {
+            return 0;
+        }
+        let buckets = match cap {
+            0..=3 => 4,
+            4..=7 => 8,
+            _ => (cap * 8 / 7).next_power_of_two(),
+        };
+        let data = (buckets * size_of::<(usize, V)>()).next_multiple_of(GROUP_WIDTH);
+        data + buckets + GROUP_WIDTH
+    }
+}
@@ -11,7 +11,9 @@
 
 use hashbrown::HashMap as HashBrownMap;
 use nohash_hasher::BuildNoHashHasher;
-use slabbable::Slabbable;
+#[cfg(feature = "stats")]
+use slabbable::SlabStats;
+use slabbable::{ReservedSlot, ResizeMode, SlabMemory, Slabbable};
 
 /// Error types
 #[derive(Debug, PartialEq)]
@@ -20,35 +22,62 @@ pub enum NoHashSlabError {
     AtCapacity(usize),
     /// Invalid index referred to
     InvalidIndex(usize),
+    /// Slots are held so the items can't be moved for resize
+    NotEmpty(usize),
+    /// The live keys would not fit the requested capacity
+    BelowOccupied(usize),
     /// Entry already exists bug internal slot mechanism took it.
     /// This is a bug and should not happen.
     BugAlreadyOccupied,
 }
 
+#[derive(Debug)]
+enum ReserveStatus<Item> {
+    Reserved,
+    Taken(Item),
+}
+
 /// Holder
 #[derive(Debug)]
 pub struct NoHashSlab<Item> {
-    inner: HashBrownMap<usize, Item, BuildNoHashHasher<usize>>,
+    inner: HashBrownMap<usize, ReserveStatus<Item>, BuildNoHashHasher<usize>>,
     // wraps
     cur: usize,
     // wraps
     rev: usize,
+    // highest ID issued before cur wraps
+    last_id: usize,
+    #[cfg(feature = "stats")]
+    stats: SlabStats,
 }
 
 impl<Item> NoHashSlab<Item> {
     fn _take_next_cur(&mut self) -> usize {
         let spot = self.cur;
-        if self.cur == usize::MAX {
+        if self.cur >= self.last_id {
             self.cur = 0;
             self.rev = match self.rev {
                 usize::MAX => 0,
                 _ => self.rev + 1,
             };
+            #[cfg(feature = "stats")]
+            self.stats.on_revolution();
         } else {
             self.cur += 1;
         }
         spot
     }
+    fn _take_next_free_cur(&mut self) -> Result<usize, NoHashSlabError> {
+        // After cur wraps the next ID may still be held by a long-lived slot.
+        // At most len() IDs can be live so within len() + 1 attempts one is free.
+        for _attempt in 0..=self.inner.len() {
+            let slot = self._take_next_cur();
+            if !self.inner.contains_key(&slot) {
+                return Ok(slot);
+            }
+        }
+        Err(NoHashSlabError::BugAlreadyOccupied)
+    }
 }
 
 impl<Item> Slabbable<NoHashSlab<Item>, Item> for NoHashSlab<Item>
@@ -58,8 +87,8 @@ where
     type Error = NoHashSlabError;
     /// See trait
     fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
-        let inner: HashBrownMap<usize, Item, BuildNoHashHasher<usize>> =
-            HashBrownMap::<usize, Item, BuildNoHashHasher<usize>>::with_capacity_and_hasher(
+        let inner: HashBrownMap<usize, ReserveStatus<Item>, BuildNoHashHasher<usize>> =
+            HashBrownMap::<usize, ReserveStatus<Item>, BuildNoHashHasher<usize>>::with_capacity_and_hasher(
                 cap,
                 BuildNoHashHasher::default(),
             );
@@ -67,18 +96,74 @@ where
             inner,
             cur: 0,
             rev: 0,
+            last_id: usize::MAX,
+            #[cfg(feature = "stats")]
+            stats: SlabStats::default(),
         })
     }
     /// See trait
     #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        // Slab re-allocators upon grow - we want stable addresses
+        if self.inner.capacity() < self.inner.len() + 1 {
+            #[cfg(feature = "stats")]
+            self.stats.on_at_capacity();
+            return Err(NoHashSlabError::AtCapacity(self.inner.capacity()));
+        }
+        let slot = self._take_next_free_cur()?;
+        match self.inner.try_insert(slot, ReserveStatus::Reserved) {
+            Ok(_) => {
+                #[cfg(feature = "stats")]
+                self.stats.on_reserve();
+                Ok(ReservedSlot::issue(slot))
+            }
+            _ => Err(NoHashSlabError::BugAlreadyOccupied),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: Item) -> Result<usize, Self::Error> {
+        let id = slot.id();
+        match self.inner.get_mut(&id) {
+            Some(status @ ReserveStatus::Reserved) => {
+                *status = ReserveStatus::Taken(with);
+                #[cfg(feature = "stats")]
+                self.stats.on_take_reserved();
+                Ok(id)
+            }
+            Some(ReserveStatus::Taken(_)) => Err(NoHashSlabError::BugAlreadyOccupied),
+            None => Err(NoHashSlabError::InvalidIndex(id)),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        let id = slot.id();
+        match self.inner.get(&id) {
+            Some(ReserveStatus::Reserved) => {}
+            _ => return Err(NoHashSlabError::InvalidIndex(id)),
+        }
+        self.inner.remove(&id);
+        #[cfg(feature = "stats")]
+        self.stats.on_cancel_reserved();
+        Ok(())
+    }
+    /// See trait
+    #[inline]
     fn take_next_with(&mut self, with: Item) -> Result<usize, Self::Error> {
         // Slab re-allocators upon grow - we want stable addresses
         if self.inner.capacity() < self.inner.len() + 1 {
+            #[cfg(feature = "stats")]
+            self.stats.on_at_capacity();
             return Err(NoHashSlabError::AtCapacity(self.inner.capacity()));
         }
-        let slot = self._take_next_cur();
-        match self.inner.try_insert(slot, with) {
-            Ok(_) => Ok(slot),
+        let slot = self._take_next_free_cur()?;
+        match self.inner.try_insert(slot, ReserveStatus::Taken(with)) {
+            Ok(_) => {
+                #[cfg(feature = "stats")]
+                self.stats.on_take();
+                Ok(slot)
+            }
             _ => Err(NoHashSlabError::BugAlreadyOccupied),
         }
     }
@@ -88,9 +173,28 @@ where
         if slot > self.inner.capacity() {
             return Err(NoHashSlabError::InvalidIndex(slot));
         }
+        match self.inner.get(&slot) {
+            Some(ReserveStatus::Taken(_)) => {}
+            _ => return Err(NoHashSlabError::InvalidIndex(slot)),
+        }
         match self.inner.remove(&slot) {
-            Some(i) => Ok(i),
-            None => Err(NoHashSlabError::InvalidIndex(slot)),
+            Some(ReserveStatus::Taken(i)) => {
+                #[cfg(feature = "stats")]
+                self.stats.on_free();
+                Ok(i)
+            }
+            _ => Err(NoHashSlabError::InvalidIndex(slot)),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut Item>, Self::Error> {
+        if slot > self.inner.capacity() {
+            return Err(NoHashSlabError::InvalidIndex(slot));
+        }
+        match self.inner.get_mut(&slot) {
+            Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
+            _ => Ok(None),
         }
     }
     /// See trait
@@ -99,7 +203,10 @@ where
         if slot > self.inner.capacity() {
             return Err(NoHashSlabError::InvalidIndex(slot));
         }
-        Ok(self.inner.get(&slot))
+        match self.inner.get(&slot) {
+            Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
+            _ => Ok(None),
+        }
     }
     /// See trait
     #[inline]
@@ -115,9 +222,94 @@ where
             1_usize.. => Some(rem),
         }
     }
+    /// See trait - the sequential IDs wrap around below the limit.
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        let Some(last_id) = limit.checked_sub(1) else {
+            return false;
+        };
+        self.last_id = last_id;
+        if self.cur > last_id {
+            self.cur = last_id;
+            self._take_next_cur();
+        }
+        true
+    }
+    /// See trait - the map is rebuilt for the new capacity moving the entries.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        let held = self.inner.len();
+        if held > 0 && mode == ResizeMode::IfEmpty {
+            return Err(NoHashSlabError::NotEmpty(held));
+        }
+        if held > new_cap {
+            return Err(NoHashSlabError::BelowOccupied(held));
+        }
+        let mut inner =
+            HashBrownMap::with_capacity_and_hasher(new_cap, BuildNoHashHasher::default());
+        inner.extend(self.inner.drain());
+        self.inner = inner;
+        Ok(())
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        SlabMemory::from_slots(
+            self.inner.allocation_size(),
+            self.inner.capacity(),
+            self.inner.len(),
+            size_of::<Item>(),
+        )
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        Some(self.stats)
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
     /// See trait
     fn reap(&mut self) -> Option<usize> {
         // We don't support it
         None
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wrap_skips_live_id() {
+        let mut slab = NoHashSlab::<u8>::with_fixed_capacity(3).unwrap();
+        // long-lived listener holds ID 0 across the wrap
+        assert_eq!(slab.take_next_with(0).unwrap(), 0);
+        slab.cur = usize::MAX;
+
+        assert_eq!(slab.take_next_with(1).unwrap(), usize::MAX);
+        assert_eq!(slab.rev, 1);
+
+        assert_eq!(slab.take_next_with(2).unwrap(), 1);
+        assert_eq!(slab.slot_get_ref(0).unwrap(), Some(&0));
+    }
+
+    #[test]
+    fn wrap_skips_consecutive_live_ids() {
+        let mut slab = NoHashSlab::<u8>::with_fixed_capacity(4).unwrap();
+        assert_eq!(slab.take_next_with(0).unwrap(), 0);
+        assert_eq!(slab.take_next_with(1).unwrap(), 1);
+        assert_eq!(slab.take_next_with(2).unwrap(), 2);
+        slab.cur = usize::MAX;
+
+        assert_eq!(slab.take_next_with(3).unwrap(), usize::MAX);
+
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(reserved.id(), 3);
+        assert_eq!(slab.take_reserved_with(reserved, 4).unwrap(), 3);
+    }
+}
@@ -9,13 +9,184 @@
 #![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
 #![doc = include_str!("../README.md")]
 
+extern crate alloc;
+
+use alloc::collections::{BinaryHeap, VecDeque};
+use core::cmp::Reverse;
 use slab::Slab;
-use slabbable::{Slabbable, SlabbableError};
+#[cfg(feature = "stats")]
+use slabbable::SlabStats;
+use slabbable::{
+    Placement, Quarantine, QuarantineQueue, ReservedSlot, ResizeMode, SlabMemory, Slabbable,
+    SlabbableError,
+};
+
+#[derive(Debug)]
+enum ReserveStatus<Item> {
+    Reserved,
+    Taken(Item),
+    // Freed but held until the quarantine is served
+    Quarantined,
+    // Free slot kept out of the slab free list to be re-used in placement order
+    Vacant,
+}
+
+// Free slots in the order they are re-used. Slab re-uses its own free list
+// most recently freed first so under Lifo the slots are handed back to it.
+#[derive(Debug)]
+enum FreeSlots {
+    Lifo,
+    LowestFree(BinaryHeap<Reverse<usize>>),
+    Fifo(VecDeque<usize>),
+}
+
+impl FreeSlots {
+    fn new(placement: Placement) -> Self {
+        match placement {
+            Placement::Lifo => Self::Lifo,
+            Placement::LowestFree => Self::LowestFree(BinaryHeap::new()),
+            Placement::Fifo => Self::Fifo(VecDeque::new()),
+        }
+    }
+    fn placement(&self) -> Placement {
+        match self {
+            Self::Lifo => Placement::Lifo,
+            Self::LowestFree(_) => Placement::LowestFree,
+            Self::Fifo(_) => Placement::Fifo,
+        }
+    }
+    fn push(&mut self, slot: usize) {
+        match self {
+            Self::Lifo => {}
+            Self::LowestFree(heap) => heap.push(Reverse(slot)),
+            Self::Fifo(queue) => queue.push_back(slot),
+        }
+    }
+    fn pop(&mut self) -> Option<usize> {
+        match self {
+            Self::Lifo => None,
+            Self::LowestFree(heap) => heap.pop().map(|Reverse(slot)| slot),
+            Self::Fifo(queue) => queue.pop_front(),
+        }
+    }
+    fn len(&self) -> usize {
+        match self {
+            Self::Lifo => 0,
+            Self::LowestFree(heap) => heap.len(),
+            Self::Fifo(queue) => queue.len(),
+        }
+    }
+}
+
+// Mirrors the private slab::Entry as slab doesn't expose the slot size so the
+// accounted bytes are a documented estimate of capacity() such entries which
+// may drift from what slab really allocates upon a slab upgrade.
+#[allow(dead_code)]
+enum SlabEntry<T> {
+    Vacant(usize),
+    Occupied(T),
+}
 
 /// Holder
 #[derive(Debug)]
 pub struct SlabSlab<Item> {
-    inner: Slab<Item>,
+    inner: Slab<ReserveStatus<Item>>,
+    // slab may allocate more than asked
+    max_capacity: usize,
+    quarantine: Option<QuarantineQueue>,
+    free: FreeSlots,
+    #[cfg(feature = "stats")]
+    stats: SlabStats,
+}
+
+impl<Item> SlabSlab<Item> {
+    // Hold the freed slot if quarantined, otherwise give it back for re-use
+    fn _free_slot(&mut self, slot: usize) -> Option<ReserveStatus<Item>> {
+        match self.quarantine.as_mut() {
+            Some(queue) => {
+                let status = self.inner.get_mut(slot)?;
+                queue.on_free(slot);
+                Some(core::mem::replace(status, ReserveStatus::Quarantined))
+            }
+            None => self._vacate(slot),
+        }
+    }
+    // Give the slot back for re-use in placement order
+    fn _vacate(&mut self, slot: usize) -> Option<ReserveStatus<Item>> {
+        if let FreeSlots::Lifo = self.free {
+            return self.inner.try_remove(slot);
+        }
+        let status = self.inner.get_mut(slot)?;
+        self.free.push(slot);
+        Some(core::mem::replace(status, ReserveStatus::Vacant))
+    }
+    // Count the operation and give back the slots that have served the quarantine
+    fn _on_op(&mut self) {
+        match self.quarantine.as_mut() {
+            Some(queue) => queue.on_op(),
+            None => return,
+        }
+        while let Some(slot) = self.quarantine.as_mut().and_then(QuarantineQueue::pop_due) {
+            self._vacate(slot);
+        }
+    }
+    // Fill the free slots of slab with vacant entries so that its own free list
+    // goes unused and the placement alone decides the slot re-used.
+    fn _claim_free(&mut self) {
+        if let FreeSlots::Lifo = self.free {
+            return;
+        }
+        while self.inner.len() < self.max_capacity {
+            let slot = self.inner.insert(ReserveStatus::Vacant);
+            self.free.push(slot);
+        }
+    }
+    // Place the status in the next free slot
+    fn _insert(&mut self, status: ReserveStatus<Item>) -> Result<usize, SlabbableError> {
+        if let Some(slot) = self.free.pop() {
+            return match self.inner.get_mut(slot) {
+                Some(vacant) => {
+                    *vacant = status;
+                    Ok(slot)
+                }
+                None => Err(SlabbableError::Bug("Vacant slot vanished")),
+            };
+        }
+        // Slab re-allocators upon grow - we want stable addresses
+        if self.max_capacity < self.inner.len() + 1 {
+            #[cfg(feature = "stats")]
+            self.stats.on_at_capacity();
+            return Err(SlabbableError::AtCapacity(self.max_capacity));
+        }
+        Ok(self.inner.insert(status))
+    }
+    // Taken, reserved or quarantined slots
+    fn _held(&self) -> usize {
+        self.inner.len() - self.free.len()
+    }
+    fn _resize(&mut self, new_cap: usize, mode: ResizeMode) -> Result<(), SlabbableError> {
+        let held = self.inner.len();
+        if held == 0 {
+            self.inner = Slab::with_capacity(new_cap);
+            self.max_capacity = new_cap;
+            return Ok(());
+        }
+        if mode == ResizeMode::IfEmpty {
+            return Err(SlabbableError::NotEmpty(held));
+        }
+        let needed = match self.inner.iter().next_back() {
+            Some((key, _)) => key + 1,
+            None => return Err(SlabbableError::Bug("Slab has len but no entries")),
+        };
+        if needed > new_cap {
+            return Err(SlabbableError::BelowOccupied(needed));
+        }
+        // drops the vacant tail so the allocation fits the capacity
+        self.inner.shrink_to_fit();
+        self.inner.reserve_exact(new_cap - self.inner.len());
+        self.max_capacity = new_cap;
+        Ok(())
+    }
 }
 
 impl<Item> Slabbable<SlabSlab<Item>, Item> for SlabSlab<Item>
@@ -27,53 +198,415 @@ where
     fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
         Ok(Self {
             inner: Slab::with_capacity(cap),
+            max_capacity: cap,
+            quarantine: None,
+            free: FreeSlots::Lifo,
+            #[cfg(feature = "stats")]
+            stats: SlabStats::default(),
         })
     }
     /// See trait
     #[inline]
-    fn take_next_with(&mut self, with: Item) -> Result<usize, Self::Error> {
-        // Slab re-allocators upon grow - we want stable addresses
-        if self.inner.capacity() < self.inner.len() + 1 {
-            return Err(SlabbableError::AtCapacity(self.inner.capacity()));
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self._on_op();
+        let slot = self._insert(ReserveStatus::Reserved)?;
+        #[cfg(feature = "stats")]
+        self.stats.on_reserve();
+        Ok(ReservedSlot::issue(slot))
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: Item) -> Result<usize, Self::Error> {
+        self._on_op();
+        let id = slot.id();
+        match self.inner.get_mut(id) {
+            Some(status @ ReserveStatus::Reserved) => {
+                *status = ReserveStatus::Taken(with);
+                #[cfg(feature = "stats")]
+                self.stats.on_take_reserved();
+                Ok(id)
+            }
+            Some(ReserveStatus::Taken(_)) => Err(SlabbableError::Bug("Key was already occupied.")),
+            Some(ReserveStatus::Quarantined | ReserveStatus::Vacant) => {
+                Err(SlabbableError::InvalidIndex(id))
+            }
+            None => Err(SlabbableError::Bug("Key was not reserved correctly.")),
         }
-        Ok(self.inner.insert(with))
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self._on_op();
+        let id = slot.id();
+        match self.inner.get(id) {
+            Some(ReserveStatus::Reserved) => {}
+            _ => return Err(SlabbableError::InvalidIndex(id)),
+        }
+        self._free_slot(id);
+        #[cfg(feature = "stats")]
+        self.stats.on_cancel_reserved();
+        Ok(())
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: Item) -> Result<usize, Self::Error> {
+        self._on_op();
+        let slot = self._insert(ReserveStatus::Taken(with))?;
+        #[cfg(feature = "stats")]
+        self.stats.on_take();
+        Ok(slot)
     }
     /// See trait
     #[inline]
     fn mark_for_reuse(&mut self, slot: usize) -> Result<Item, Self::Error> {
-        if slot > self.inner.capacity() {
+        self._on_op();
+        if slot > self.max_capacity {
             return Err(SlabbableError::InvalidIndex(slot));
         }
-        match self.inner.try_remove(slot) {
-            Some(i) => Ok(i),
-            None => Err(SlabbableError::InvalidIndex(slot)),
+        match self.inner.get(slot) {
+            Some(ReserveStatus::Taken(_)) => {}
+            _ => return Err(SlabbableError::InvalidIndex(slot)),
+        }
+        match self._free_slot(slot) {
+            Some(ReserveStatus::Taken(i)) => {
+                #[cfg(feature = "stats")]
+                self.stats.on_free();
+                Ok(i)
+            }
+            _ => Err(SlabbableError::Bug("Taken slot vanished upon remove")),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut Item>, Self::Error> {
+        if slot > self.max_capacity {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        match self.inner.get_mut(slot) {
+            Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
+            _ => Ok(None),
         }
     }
     /// See trait
     #[inline]
     fn slot_get_ref(&self, slot: usize) -> Result<Option<&Item>, Self::Error> {
-        if slot > self.inner.capacity() {
+        if slot > self.max_capacity {
             return Err(SlabbableError::InvalidIndex(slot));
         }
-        Ok(self.inner.get(slot))
+        match self.inner.get(slot) {
+            Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
+            _ => Ok(None),
+        }
     }
     /// See trait
     #[inline]
     fn capacity(&self) -> usize {
-        self.inner.capacity()
+        self.max_capacity
     }
     /// See trait
     #[inline]
     fn remaining(&self) -> Option<usize> {
-        let rem = self.inner.capacity() - self.inner.len();
+        let rem = self.max_capacity - self._held();
         match rem {
             0 => None,
             1_usize.. => Some(rem),
         }
     }
+    /// See trait - the quarantined slots stay occupied.
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        match (quarantine, self.quarantine.as_mut()) {
+            (Some(quarantine), Some(queue)) => queue.set_quarantine(quarantine),
+            (Some(quarantine), None) => self.quarantine = Some(QuarantineQueue::new(quarantine)),
+            (None, _) => {
+                let mut queue = match self.quarantine.take() {
+                    Some(queue) => queue,
+                    None => return,
+                };
+                while let Some(slot) = queue.pop_any() {
+                    self._vacate(slot);
+                }
+            }
+        }
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        Some(self.free.placement())
+    }
+    /// See trait - slab re-uses the most recently freed key itself, otherwise
+    /// all the free slots are held as vacant entries in placement order.
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        if placement == self.free.placement() {
+            return true;
+        }
+        while let Some(slot) = self.free.pop() {
+            self.inner.try_remove(slot);
+        }
+        self.free = FreeSlots::new(placement);
+        self._claim_free();
+        true
+    }
+    /// See trait - the keys are indices so the highest live key must fit.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        // the vacant entries are handed back to slab and claimed again after
+        let placement = self.free.placement();
+        self.set_placement(Placement::Lifo);
+        let resized = self._resize(new_cap, mode);
+        self.set_placement(placement);
+        resized
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        let cap = self.inner.capacity();
+        SlabMemory::from_slots(
+            cap * size_of::<SlabEntry<ReserveStatus<Item>>>(),
+            cap,
+            self._held(),
+            size_of::<Item>(),
+        )
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        Some(self.stats)
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
     /// See trait
     fn reap(&mut self) -> Option<usize> {
         // We don't support it
         None
     }
 }
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    fn check_entry_bytes<T: core::fmt::Debug + Clone>(cap: usize) {
+        let mut slab = SlabSlab::<T>::with_fixed_capacity(cap).unwrap();
+        let mem = slab.memory_usage();
+        assert_eq!(
+            mem.allocated_bytes,
+            slab.inner.capacity() * size_of::<SlabEntry<ReserveStatus<T>>>()
+        );
+        assert!(mem.allocated_bytes >= cap * size_of::<ReserveStatus<T>>());
+        slab.try_resize_fixed_capacity(cap * 2, ResizeMode::IfEmpty)
+            .unwrap();
+        assert_eq!(
+            slab.memory_usage().allocated_bytes,
+            slab.inner.capacity() * size_of::<SlabEntry<ReserveStatus<T>>>()
+        );
+    }
+
+    #[test]
+    fn memory_usage_follows_slab_capacity() {
+        check_entry_bytes::<u8>(64);
+        check_entry_bytes::<u64>(64);
+        check_entry_bytes::<[u8; 13]>(64);
+    }
+
+    #[test]
+    fn reserve_and_memory_usage() {
+        let mut slab = SlabSlab::<u64>::with_fixed_capacity(4).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(slab.slot_get_ref(reserved.id()).unwrap(), None);
+        assert_eq!(
+            slab.mark_for_reuse(reserved.id()),
+            Err(SlabbableError::InvalidIndex(reserved.id()))
+        );
+        let key = slab.take_reserved_with(reserved, 7).unwrap();
+        *slab.slot_get_mut(key).unwrap().unwrap() += 1;
+        assert_eq!(slab.slot_get_ref(key).unwrap(), Some(&8));
+
+        let mem = slab.memory_usage();
+        assert!(mem.allocated_bytes >= 4 * size_of::<u64>());
+        assert_eq!(mem.live_bytes, size_of::<u64>() + mem.overhead_per_slot);
+        assert_eq!(slab.mark_for_reuse(key).unwrap(), 8);
+        assert_eq!(slab.memory_usage().live_bytes, 0);
+    }
+
+    #[test]
+    fn resize_keeps_keys() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(4).unwrap();
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        slab.mark_for_reuse(keys[0]).unwrap();
+        assert_eq!(
+            slab.try_resize_fixed_capacity(8, ResizeMode::IfEmpty),
+            Err(SlabbableError::NotEmpty(2))
+        );
+        assert_eq!(
+            slab.try_resize_fixed_capacity(2, ResizeMode::NoAddressesHeld),
+            Err(SlabbableError::BelowOccupied(3))
+        );
+        slab.try_resize_fixed_capacity(3, ResizeMode::NoAddressesHeld)
+            .unwrap();
+        assert_eq!(slab.capacity(), 3);
+        assert_eq!(slab.slot_get_ref(keys[2]).unwrap(), Some(&2));
+        assert_eq!(slab.take_next_with(3).unwrap(), keys[0]);
+        assert_eq!(slab.take_next_with(4), Err(SlabbableError::AtCapacity(3)));
+
+        slab.try_resize_fixed_capacity(6, ResizeMode::NoAddressesHeld)
+            .unwrap();
+        assert_eq!(slab.remaining(), Some(3));
+    }
+
+    #[test]
+    fn quarantine_never_aliases_stale_key() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(4).unwrap();
+        slab.set_quarantine(Some(Quarantine {
+            frees: 2,
+            ops: usize::MAX,
+        }));
+        let stale = slab.take_next_with(0).unwrap();
+        slab.mark_for_reuse(stale).unwrap();
+
+        // slab would hand the most recently freed key right back
+        assert_eq!(slab.slot_get_ref(stale).unwrap(), None);
+        assert_eq!(
+            slab.mark_for_reuse(stale),
+            Err(SlabbableError::InvalidIndex(stale))
+        );
+        let a = slab.take_next_with(1).unwrap();
+        assert_ne!(a, stale);
+        slab.mark_for_reuse(a).unwrap();
+        let b = slab.take_next_with(2).unwrap();
+        assert!(b != stale && b != a);
+        slab.mark_for_reuse(b).unwrap();
+
+        // two other frees served the quarantine of the stale key
+        assert_eq!(slab.take_next_with(3).unwrap(), stale);
+
+        slab.set_quarantine(None);
+        assert_eq!(slab.remaining(), Some(3));
+    }
+
+    #[test]
+    fn quarantine_counts_take_reserved() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(4).unwrap();
+        slab.set_quarantine(Some(Quarantine {
+            frees: usize::MAX,
+            ops: 3,
+        }));
+        let stale = slab.take_next_with(0).unwrap();
+        slab.mark_for_reuse(stale).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        assert_ne!(reserved.id(), stale);
+        slab.take_reserved_with(reserved, 1).unwrap();
+        // the reserve, its take and this take served the quarantine
+        assert_eq!(slab.take_next_with(2).unwrap(), stale);
+    }
+
+    #[test]
+    fn placement_lifo() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(4).unwrap();
+        assert_eq!(slab.placement(), Some(Placement::Lifo));
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        slab.mark_for_reuse(keys[0]).unwrap();
+        slab.mark_for_reuse(keys[2]).unwrap();
+        assert_eq!(slab.take_next_with(3).unwrap(), keys[2]);
+    }
+
+    #[test]
+    fn placement_lowest_free() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(4).unwrap();
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        slab.mark_for_reuse(keys[0]).unwrap();
+        slab.mark_for_reuse(keys[2]).unwrap();
+        assert!(slab.set_placement(Placement::LowestFree));
+        assert_eq!(slab.placement(), Some(Placement::LowestFree));
+        assert_eq!(slab.remaining(), Some(3));
+        let mem = slab.memory_usage();
+        assert_eq!(mem.live_bytes, size_of::<u8>() + mem.overhead_per_slot);
+
+        assert_eq!(slab.slot_get_ref(keys[0]).unwrap(), None);
+        assert_eq!(
+            slab.mark_for_reuse(keys[0]),
+            Err(SlabbableError::InvalidIndex(keys[0]))
+        );
+        assert_eq!(slab.take_next_with(3).unwrap(), 0);
+        slab.mark_for_reuse(keys[1]).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(reserved.id(), 1);
+        slab.cancel_reserved(reserved).unwrap();
+        assert_eq!(slab.take_next_with(4).unwrap(), 1);
+        assert_eq!(slab.take_next_with(5).unwrap(), 2);
+        assert_eq!(slab.take_next_with(6).unwrap(), 3);
+        assert_eq!(slab.take_next_with(7), Err(SlabbableError::AtCapacity(4)));
+        assert_eq!(slab.remaining(), None);
+    }
+
+    #[test]
+    fn placement_fifo() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(3).unwrap();
+        assert!(slab.set_placement(Placement::Fifo));
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        slab.mark_for_reuse(keys[2]).unwrap();
+        slab.mark_for_reuse(keys[0]).unwrap();
+        assert_eq!(slab.take_next_with(3).unwrap(), keys[2]);
+        slab.mark_for_reuse(keys[1]).unwrap();
+        assert_eq!(slab.take_next_with(4).unwrap(), keys[0]);
+        assert_eq!(slab.take_next_with(5).unwrap(), keys[1]);
+
+        // back to slab's own free list
+        assert!(slab.set_placement(Placement::Lifo));
+        slab.mark_for_reuse(keys[0]).unwrap();
+        slab.mark_for_reuse(keys[1]).unwrap();
+        assert_eq!(slab.take_next_with(6).unwrap(), keys[1]);
+    }
+
+    #[test]
+    fn placement_after_quarantine_and_resize() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(4).unwrap();
+        assert!(slab.set_placement(Placement::LowestFree));
+        slab.set_quarantine(Some(Quarantine {
+            frees: 1,
+            ops: usize::MAX,
+        }));
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        slab.mark_for_reuse(keys[0]).unwrap();
+        assert_eq!(slab.take_next_with(3).unwrap(), 3);
+        // the next free serves the quarantine of the lowest key
+        slab.mark_for_reuse(keys[1]).unwrap();
+        assert_eq!(slab.take_next_with(4).unwrap(), keys[0]);
+        slab.set_quarantine(None);
+
+        slab.try_resize_fixed_capacity(6, ResizeMode::NoAddressesHeld)
+            .unwrap();
+        assert_eq!(slab.placement(), Some(Placement::LowestFree));
+        assert_eq!(slab.remaining(), Some(3));
+        assert_eq!(slab.take_next_with(5).unwrap(), keys[1]);
+        assert_eq!(slab.take_next_with(6).unwrap(), 4);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() {
+        let mut slab = SlabSlab::<u8>::with_fixed_capacity(2).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        let a = slab.take_next_with(1).unwrap();
+        assert_eq!(slab.reserve_next(), Err(SlabbableError::AtCapacity(2)));
+        slab.take_reserved_with(reserved, 0).unwrap();
+        slab.mark_for_reuse(a).unwrap();
+
+        assert_eq!(
+            slab.stats(),
+            Some(SlabStats {
+                takes: 2,
+                frees: 1,
+                live: 1,
+                reserved: 0,
+                high_watermark: 2,
+                at_capacity: 1,
+                revolutions: 0,
+            })
+        );
+    }
+}
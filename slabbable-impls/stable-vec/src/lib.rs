@@ -17,15 +17,299 @@
 //!
 //! This impl of StableVec re-uses idx unlike impl that keeps track of rotating
 //! index within.
+//!
+//! The capacity is split into segments of StableVec each allocated with a fixed
+//! number of slots. A segment is only ever allocated or freed whole so growing
+//! or reaping never moves the items living in the other segments.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 
-use slabbable::{Slabbable, SlabbableError};
+use slabbable::{
+    Placement, Quarantine, QuarantineQueue, ReapAction, ReapBudget, ReapPolicy, ReapProgress,
+    ReapTracker, Relocation, ReservedSlot, ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use slabbable::SlabStats;
 
 use stable_vec::{core::BitVecCore, StableVecFacade};
 
+/// Default number of slots per segment
+pub const DEFAULT_SEGMENT_SLOTS: usize = 4096;
+
+#[derive(Debug)]
+enum ReserveStatus<Item> {
+    Reserved,
+    Taken(Item),
+    // Freed but held until the quarantine is served
+    Quarantined,
+}
+
+type Segment<Item> = StableVecFacade<ReserveStatus<Item>, BitVecCore<ReserveStatus<Item>>>;
+
 /// Holder
 #[derive(Debug)]
 pub struct StableVecSlab<Item> {
-    inner: StableVecFacade<Item, BitVecCore<Item>>,
+    // None = segment released by reap() and re-allocated upon demand
+    segments: Vec<Option<Segment<Item>>>,
+    segment_slots: usize,
+    max_capacity: usize,
+    // Reserved + Taken across all segments
+    occupied: usize,
+    // Lowest segment that may have a free slot
+    free_hint: usize,
+    placement: Placement,
+    // Slots in the order freed for Fifo / Lifo, may hold slots taken since
+    freed: VecDeque<usize>,
+    quarantine: Option<QuarantineQueue>,
+    // Segment where an unfinished reap_bounded() continues from
+    reap_cursor: Option<usize>,
+    reap_tracker: Option<ReapTracker>,
+    #[cfg(feature = "stats")]
+    stats: SlabStats,
+}
+
+impl<Item> StableVecSlab<Item> {
+    /// Provided with capacity split into segments of segment_slots each.
+    /// Smaller segments let reap() give back memory in finer steps.
+    pub fn with_fixed_capacity_and_segment_slots(
+        cap: usize,
+        segment_slots: usize,
+    ) -> Result<Self, SlabbableError> {
+        let segment_slots = segment_slots.max(1);
+        let num_segments = cap.div_ceil(segment_slots);
+        let mut segments = Vec::with_capacity(num_segments);
+        for seg in 0..num_segments {
+            segments.push(Some(Self::_alloc_segment(cap, segment_slots, seg)));
+        }
+        Ok(Self {
+            segments,
+            segment_slots,
+            max_capacity: cap,
+            occupied: 0,
+            free_hint: 0,
+            placement: Placement::LowestFree,
+            freed: VecDeque::new(),
+            quarantine: None,
+            reap_cursor: None,
+            reap_tracker: None,
+            #[cfg(feature = "stats")]
+            stats: SlabStats::default(),
+        })
+    }
+    #[inline]
+    fn _segment_len(cap: usize, segment_slots: usize, seg: usize) -> usize {
+        core::cmp::min(segment_slots, cap - seg * segment_slots)
+    }
+    // Element storage plus the BitVecCore usize words marking filled slots
+    #[inline]
+    fn _segment_bytes(segment: &Segment<Item>) -> usize {
+        let slots = segment.capacity();
+        slots * size_of::<ReserveStatus<Item>>()
+            + slots.div_ceil(usize::BITS as usize) * size_of::<usize>()
+    }
+    fn _alloc_segment(cap: usize, segment_slots: usize, seg: usize) -> Segment<Item> {
+        Segment::<Item>::with_capacity(Self::_segment_len(cap, segment_slots, seg))
+    }
+    #[inline]
+    fn _split(&self, slot: usize) -> Result<(usize, usize), SlabbableError> {
+        if slot >= self.max_capacity {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        Ok((slot / self.segment_slots, slot % self.segment_slots))
+    }
+    // Free slot as per the placement, allocating the segment if it was reaped.
+    fn _insert(&mut self, status: ReserveStatus<Item>) -> Result<usize, SlabbableError> {
+        if self.occupied == self.max_capacity {
+            #[cfg(feature = "stats")]
+            self.stats.on_at_capacity();
+            return Err(SlabbableError::AtCapacity(self.max_capacity));
+        }
+        let slot = match self._pop_freed() {
+            Some(slot) => slot,
+            None => match self._lowest_free_from(self.free_hint * self.segment_slots) {
+                Some(slot) => slot,
+                None => {
+                    return Err(SlabbableError::Bug(
+                        "Occupied count out of sync with segments",
+                    ))
+                }
+            },
+        };
+        let (cap, segment_slots) = (self.max_capacity, self.segment_slots);
+        let (seg, idx) = (slot / segment_slots, slot % segment_slots);
+        let segment =
+            self.segments[seg].get_or_insert_with(|| Self::_alloc_segment(cap, segment_slots, seg));
+        #[cfg(feature = "stats")]
+        match status {
+            ReserveStatus::Reserved => self.stats.on_reserve(),
+            ReserveStatus::Taken(_) => self.stats.on_take(),
+            ReserveStatus::Quarantined => {}
+        }
+        segment.insert(idx, status);
+        self.occupied += 1;
+        if self.placement == Placement::LowestFree {
+            self.free_hint = seg;
+        }
+        if let Some(tracker) = self.reap_tracker.as_mut() {
+            tracker.on_take(self.occupied);
+        }
+        Ok(slot)
+    }
+    // Next freed slot for Fifo / Lifo skipping the ones taken or resized away since
+    fn _pop_freed(&mut self) -> Option<usize> {
+        loop {
+            let slot = match self.placement {
+                Placement::LowestFree => return None,
+                Placement::Fifo => self.freed.pop_front()?,
+                Placement::Lifo => self.freed.pop_back()?,
+            };
+            if slot >= self.max_capacity {
+                continue;
+            }
+            let (seg, idx) = (slot / self.segment_slots, slot % self.segment_slots);
+            match &self.segments[seg] {
+                Some(segment) if segment.has_element_at(idx) => continue,
+                _ => return Some(slot),
+            }
+        }
+    }
+    // Hold the freed slot if quarantined, otherwise give it back for re-use
+    fn _free_slot(&mut self, slot: usize) -> Option<ReserveStatus<Item>> {
+        let (seg, idx) = (slot / self.segment_slots, slot % self.segment_slots);
+        let segment = self.segments[seg].as_mut()?;
+        if let Some(queue) = self.quarantine.as_mut() {
+            let status = segment.get_mut(idx)?;
+            queue.on_free(slot);
+            return Some(core::mem::replace(status, ReserveStatus::Quarantined));
+        }
+        let status = segment.remove(idx)?;
+        self._vacated(slot);
+        Some(status)
+    }
+    // Count the operation and give back the slots that have served the quarantine
+    fn _on_op(&mut self) {
+        let queue = match self.quarantine.as_mut() {
+            Some(queue) => queue,
+            None => return,
+        };
+        queue.on_op();
+        while let Some(slot) = self.quarantine.as_mut().and_then(QuarantineQueue::pop_due) {
+            self._release(slot);
+        }
+    }
+    fn _release(&mut self, slot: usize) {
+        let (seg, idx) = (slot / self.segment_slots, slot % self.segment_slots);
+        let segment = match self.segments[seg].as_mut() {
+            Some(segment) => segment,
+            None => return,
+        };
+        if segment.remove(idx).is_some() {
+            self._vacated(slot);
+        }
+    }
+    #[inline]
+    fn _vacated(&mut self, slot: usize) {
+        self.occupied -= 1;
+        self._on_freed(slot);
+        self._on_free();
+    }
+    fn _on_freed(&mut self, slot: usize) {
+        let seg = slot / self.segment_slots;
+        if seg < self.free_hint {
+            self.free_hint = seg;
+        }
+        if self.placement != Placement::LowestFree {
+            self.freed.push_back(slot);
+        }
+    }
+    // Highest Reserved or Taken slot
+    fn _highest_slot(&self) -> Option<usize> {
+        self.segments
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(seg, segment)| {
+                let segment = segment.as_ref()?;
+                let idx = segment.first_filled_slot_below(segment.next_push_index())?;
+                Some(seg * self.segment_slots + idx)
+            })
+    }
+    // Lowest free slot at or above the given one, reaped segments are all free
+    fn _lowest_free_from(&self, from: usize) -> Option<usize> {
+        let (cap, segment_slots) = (self.max_capacity, self.segment_slots);
+        for seg in from / segment_slots..self.segments.len() {
+            let start = from.saturating_sub(seg * segment_slots);
+            let seg_len = Self::_segment_len(cap, segment_slots, seg);
+            let segment = match &self.segments[seg] {
+                Some(segment) => segment,
+                None => return Some(seg * segment_slots + start),
+            };
+            if segment.num_elements() == seg_len {
+                continue;
+            }
+            // No holes below the push index is the common case when filling up
+            // and spares the linear scan
+            let idx = match segment.is_compact() {
+                true => segment.next_push_index().max(start),
+                false => match segment.first_empty_slot_from(start) {
+                    Some(idx) if idx < seg_len => idx,
+                    // never pushed this far yet
+                    _ => segment.next_push_index().max(start),
+                },
+            };
+            if idx < seg_len {
+                return Some(seg * segment_slots + idx);
+            }
+        }
+        None
+    }
+    fn _reap_segments(&mut self, budget: ReapBudget) -> ReapProgress {
+        let budget = budget.as_slots(size_of::<ReserveStatus<Item>>());
+        let mut reaped = 0;
+        let mut seg = self.reap_cursor.take().unwrap_or(self.segments.len());
+        while seg > 0 {
+            seg -= 1;
+            match &self.segments[seg] {
+                None => continue,
+                Some(segment) if segment.is_empty() => {
+                    let seg_len = Self::_segment_len(self.max_capacity, self.segment_slots, seg);
+                    if reaped > 0 && reaped + seg_len > budget {
+                        self.reap_cursor = Some(seg + 1);
+                        return ReapProgress::Pending(reaped);
+                    }
+                    reaped += seg_len;
+                    self.segments[seg] = None;
+                }
+                Some(_) => break,
+            }
+        }
+        if let Some(tracker) = self.reap_tracker.as_mut() {
+            tracker.reaped(self.occupied);
+        }
+        ReapProgress::Done(reaped)
+    }
+    // Evaluate the reap policy upon freeing a slot
+    #[inline]
+    fn _on_free(&mut self) {
+        let tracker = match self.reap_tracker.as_mut() {
+            Some(tracker) => tracker,
+            None => return,
+        };
+        if !tracker.on_free(self.occupied) {
+            return;
+        }
+        let action = tracker.policy().action;
+        match action {
+            ReapAction::Signal => {}
+            ReapAction::Bounded(budget) => {
+                let _ = self._reap_segments(budget);
+            }
+        }
+    }
 }
 
 impl<Item> Slabbable<StableVecSlab<Item>, Item> for StableVecSlab<Item>
@@ -35,55 +319,713 @@ where
     type Error = SlabbableError;
     /// See trait
     fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
-        Ok(Self {
-            inner: StableVecFacade::<Item, BitVecCore<Item>>::with_capacity(cap),
-        })
+        Self::with_fixed_capacity_and_segment_slots(cap, DEFAULT_SEGMENT_SLOTS)
     }
     /// See trait
     #[inline]
-    fn take_next_with(&mut self, with: Item) -> Result<usize, Self::Error> {
-        // StableVec re-allocators upon grow - we want stable addresses
-        if self.inner.capacity() < self.inner.num_elements() + 1 {
-            return Err(SlabbableError::AtCapacity(self.inner.capacity()));
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self._on_op();
+        let slot = self._insert(ReserveStatus::Reserved)?;
+        Ok(ReservedSlot::issue(slot))
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: Item) -> Result<usize, Self::Error> {
+        self._on_op();
+        let id = slot.id();
+        let (seg, idx) = self._split(id)?;
+        match self.segments[seg].as_mut().and_then(|s| s.get_mut(idx)) {
+            Some(status @ ReserveStatus::Reserved) => {
+                *status = ReserveStatus::Taken(with);
+                #[cfg(feature = "stats")]
+                self.stats.on_take_reserved();
+                Ok(id)
+            }
+            Some(ReserveStatus::Taken(_)) => Err(SlabbableError::Bug("Key was already occupied.")),
+            Some(ReserveStatus::Quarantined) => Err(SlabbableError::InvalidIndex(id)),
+            None => Err(SlabbableError::Bug("Key was not reserved correctly.")),
         }
-        Ok(self.inner.push(with))
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self._on_op();
+        let id = slot.id();
+        let (seg, idx) = self._split(id)?;
+        match self.segments[seg].as_ref().and_then(|s| s.get(idx)) {
+            Some(ReserveStatus::Reserved) => {}
+            _ => return Err(SlabbableError::InvalidIndex(id)),
+        }
+        #[cfg(feature = "stats")]
+        self.stats.on_cancel_reserved();
+        self._free_slot(id);
+        Ok(())
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: Item) -> Result<usize, Self::Error> {
+        self._on_op();
+        self._insert(ReserveStatus::Taken(with))
     }
     /// See trait
     #[inline]
     fn mark_for_reuse(&mut self, slot: usize) -> Result<Item, Self::Error> {
-        if slot > self.inner.capacity() {
-            return Err(SlabbableError::InvalidIndex(slot));
+        self._on_op();
+        let (seg, idx) = self._split(slot)?;
+        match self.segments[seg].as_ref().and_then(|s| s.get(idx)) {
+            Some(ReserveStatus::Taken(_)) => {}
+            _ => return Err(SlabbableError::InvalidIndex(slot)),
+        }
+        #[cfg(feature = "stats")]
+        self.stats.on_free();
+        match self._free_slot(slot) {
+            Some(ReserveStatus::Taken(i)) => Ok(i),
+            _ => Err(SlabbableError::Bug("Taken slot vanished upon remove")),
         }
-        match self.inner.remove(slot) {
-            Some(i) => Ok(i),
-            None => Err(SlabbableError::InvalidIndex(slot)),
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut Item>, Self::Error> {
+        let (seg, idx) = self._split(slot)?;
+        match self.segments[seg].as_mut().and_then(|s| s.get_mut(idx)) {
+            Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
+            _ => Ok(None),
         }
     }
     /// See trait
     #[inline]
     fn slot_get_ref(&self, slot: usize) -> Result<Option<&Item>, Self::Error> {
-        if slot > self.inner.capacity() {
-            return Err(SlabbableError::InvalidIndex(slot));
+        let (seg, idx) = self._split(slot)?;
+        match self.segments[seg].as_ref().and_then(|s| s.get(idx)) {
+            Some(ReserveStatus::Taken(itm_ref)) => Ok(Some(itm_ref)),
+            _ => Ok(None),
         }
-        Ok(self.inner.get(slot))
     }
     /// See trait
     #[inline]
     fn capacity(&self) -> usize {
-        self.inner.capacity()
+        self.max_capacity
     }
     /// See trait
     #[inline]
     fn remaining(&self) -> Option<usize> {
-        let rem = self.inner.capacity() - self.inner.num_elements();
+        let rem = self.max_capacity - self.occupied;
         match rem {
             0 => None,
             1_usize.. => Some(rem),
         }
     }
+    /// See trait - the keys are indices so the highest live key must fit.
+    /// Only the segment(s) whose length changes move their items.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        if self.occupied > 0 && mode == ResizeMode::IfEmpty {
+            return Err(SlabbableError::NotEmpty(self.occupied));
+        }
+        let needed = self._highest_slot().map_or(0, |slot| slot + 1);
+        if needed > new_cap {
+            return Err(SlabbableError::BelowOccupied(needed));
+        }
+        let (old_cap, segment_slots) = (self.max_capacity, self.segment_slots);
+        let num_segments = new_cap.div_ceil(segment_slots);
+        // the segments beyond are empty as per the highest slot
+        self.segments.truncate(num_segments);
+        for seg in 0..num_segments {
+            let seg_len = Self::_segment_len(new_cap, segment_slots, seg);
+            let segment = match self.segments.get_mut(seg) {
+                Some(Some(segment)) => segment,
+                // reaped segments are allocated with the new length upon demand
+                Some(None) => continue,
+                None => {
+                    self.segments
+                        .push(Some(Self::_alloc_segment(new_cap, segment_slots, seg)));
+                    continue;
+                }
+            };
+            if Self::_segment_len(old_cap, segment_slots, seg) == seg_len {
+                continue;
+            }
+            let mut resized = Self::_alloc_segment(new_cap, segment_slots, seg);
+            while let Some(idx) = segment.first_filled_slot_from(0) {
+                if let Some(status) = segment.remove(idx) {
+                    resized.insert(idx, status);
+                }
+            }
+            *segment = resized;
+        }
+        self.max_capacity = new_cap;
+        self.free_hint = 0;
+        self.reap_cursor = None;
+        Ok(())
+    }
+    /// See trait - the quarantined slots stay occupied.
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        match (quarantine, self.quarantine.as_mut()) {
+            (Some(quarantine), Some(queue)) => queue.set_quarantine(quarantine),
+            (Some(quarantine), None) => self.quarantine = Some(QuarantineQueue::new(quarantine)),
+            (None, _) => {
+                let mut queue = match self.quarantine.take() {
+                    Some(queue) => queue,
+                    None => return,
+                };
+                while let Some(slot) = queue.pop_any() {
+                    self._release(slot);
+                }
+            }
+        }
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        Some(self.placement)
+    }
+    /// See trait - Fifo and Lifo fall back to the lowest free slot until
+    /// slots have been freed under them.
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        if placement == Placement::LowestFree {
+            self.freed = VecDeque::new();
+            self.free_hint = 0;
+        }
+        self.placement = placement;
+        true
+    }
+    /// See trait - the keys are indices so the moved items change key. Moves
+    /// from the highest slots into the lowest free ones until they meet.
+    fn compact<M, R>(&mut self, mut movable: M, mut relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &Item) -> bool,
+        R: FnMut(Relocation, &Item),
+    {
+        let (cap, segment_slots) = (self.max_capacity, self.segment_slots);
+        let mut moved = 0;
+        let mut free = 0;
+        let mut high = self._highest_slot().map_or(0, |slot| slot + 1);
+        while high > 0 {
+            high -= 1;
+            free = match self._lowest_free_from(free) {
+                Some(to) if to < high => to,
+                _ => break,
+            };
+            let (seg, idx) = (high / segment_slots, high % segment_slots);
+            let segment = match self.segments[seg].as_mut() {
+                Some(segment) => segment,
+                None => continue,
+            };
+            match segment.get(idx) {
+                Some(ReserveStatus::Taken(item)) if movable(high, item) => {}
+                _ => continue,
+            }
+            let status = match segment.remove(idx) {
+                Some(status) => status,
+                None => continue,
+            };
+            let (to_seg, to_idx) = (free / segment_slots, free % segment_slots);
+            let target = self.segments[to_seg]
+                .get_or_insert_with(|| Self::_alloc_segment(cap, segment_slots, to_seg));
+            target.insert(to_idx, status);
+            if let Some(ReserveStatus::Taken(item)) = target.get(to_idx) {
+                let relocation = Relocation {
+                    old_key: high,
+                    new_key: free,
+                };
+                relocated(relocation, item);
+            }
+            moved += 1;
+            self._on_freed(high);
+            self._on_free();
+        }
+        // the moved into slots are no longer free
+        let (segments, segment_slots) = (&self.segments, self.segment_slots);
+        self.freed
+            .retain(|slot| match segments.get(slot / segment_slots) {
+                Some(Some(segment)) => !segment.has_element_at(slot % segment_slots),
+                _ => true,
+            });
+        self.free_hint = 0;
+        Some(moved)
+    }
     /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        let mut allocated_bytes = 0;
+        let mut allocated_slots = 0;
+        let mut reclaimable_bytes = 0;
+        let mut trailing = true;
+        for segment in self.segments.iter().rev().flatten() {
+            let bytes = Self::_segment_bytes(segment);
+            trailing = trailing && segment.is_empty();
+            if trailing {
+                reclaimable_bytes += bytes;
+            }
+            allocated_bytes += bytes;
+            allocated_slots += segment.capacity();
+        }
+        SlabMemory {
+            reclaimable_bytes,
+            ..SlabMemory::from_slots(
+                allocated_bytes,
+                allocated_slots,
+                self.occupied,
+                size_of::<Item>(),
+            )
+        }
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        Some(self.stats)
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.stats.reset();
+    }
+    /// See trait - releases the trailing empty segments. Returns the slots released.
     fn reap(&mut self) -> Option<usize> {
-        // We don't support it
-        None
+        self.reap_cursor = None;
+        match self.reap_bounded(ReapBudget::Slots(usize::MAX)) {
+            ReapProgress::Done(reaped) | ReapProgress::Pending(reaped) => Some(reaped),
+            ReapProgress::Unsupported => None,
+        }
+    }
+    /// See trait - releases trailing empty segments until the budget is spent.
+    /// At least one segment is released per call even if it exceeds the budget.
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self._reap_segments(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.reap_tracker = policy.map(|policy| {
+            let mut tracker = ReapTracker::new(policy);
+            tracker.on_take(self.occupied);
+            tracker
+        });
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        match &self.reap_tracker {
+            Some(tracker) => tracker.is_due(),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reap_trailing_segments() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(10, 4).unwrap();
+        let keys: Vec<usize> = (0..10).map(|i| slab.take_next_with(i).unwrap()).collect();
+        assert_eq!(keys, (0..10).collect::<Vec<usize>>());
+        assert_eq!(slab.reap(), Some(0));
+
+        // ramp-down leaving 0 and 5 alive
+        for key in keys.iter().filter(|k| **k != 0 && **k != 5) {
+            slab.mark_for_reuse(*key).unwrap();
+        }
+        let ptr_0 = core::ptr::addr_of!(*slab.slot_get_ref(0).unwrap().unwrap());
+
+        // only the last segment of 2 slots is trailing-free
+        assert_eq!(slab.reap(), Some(2));
+        assert_eq!(slab.reap(), Some(0));
+        assert_eq!(slab.slot_get_ref(9).unwrap(), None);
+
+        slab.mark_for_reuse(5).unwrap();
+        assert_eq!(slab.reap(), Some(4));
+        assert_eq!(slab.capacity(), 10);
+        assert_eq!(slab.remaining(), Some(9));
+
+        assert_eq!(
+            ptr_0,
+            core::ptr::addr_of!(*slab.slot_get_ref(0).unwrap().unwrap())
+        );
+    }
+
+    #[test]
+    fn grow_after_reap_up_to_fixed_capacity() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(6, 2).unwrap();
+        let first = slab.take_next_with(0).unwrap();
+        assert_eq!(slab.reap(), Some(4));
+        let ptr = core::ptr::addr_of!(*slab.slot_get_ref(first).unwrap().unwrap());
+
+        for i in 1..6 {
+            assert_eq!(slab.take_next_with(i).unwrap(), i as usize);
+        }
+        assert_eq!(slab.take_next_with(6), Err(SlabbableError::AtCapacity(6)));
+        assert_eq!(
+            ptr,
+            core::ptr::addr_of!(*slab.slot_get_ref(first).unwrap().unwrap())
+        );
+    }
+
+    #[test]
+    fn reserved_blocks_reap() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(4, 2).unwrap();
+        slab.take_next_with(0).unwrap();
+        slab.take_next_with(1).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(reserved.id(), 2);
+        assert_eq!(slab.reap(), Some(0));
+        assert_eq!(slab.slot_get_ref(2).unwrap(), None);
+        assert_eq!(slab.take_reserved_with(reserved, 2).unwrap(), 2);
+        assert_eq!(slab.mark_for_reuse(4), Err(SlabbableError::InvalidIndex(4)));
+    }
+
+    #[test]
+    fn reap_bounded_resumes() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(10, 2).unwrap();
+        slab.take_next_with(0).unwrap();
+
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Slots(3)),
+            ReapProgress::Pending(2)
+        );
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Slots(4)),
+            ReapProgress::Pending(4)
+        );
+        // the budget is smaller than a segment but progress is still made
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Slots(1)),
+            ReapProgress::Done(2)
+        );
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Slots(1)),
+            ReapProgress::Done(0)
+        );
+        assert_eq!(slab.slot_get_ref(0).unwrap(), Some(&0));
+    }
+
+    #[test]
+    fn reap_bounded_done_when_budget_meets_live_segment() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(6, 2).unwrap();
+        slab.take_next_with(0).unwrap();
+        // the budget runs out exactly where the live segment starts
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Slots(4)),
+            ReapProgress::Done(4)
+        );
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Slots(4)),
+            ReapProgress::Done(0)
+        );
+    }
+
+    #[test]
+    fn reap_bounded_stops_at_live_segment() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(8, 2).unwrap();
+        for i in 0..8 {
+            slab.take_next_with(i).unwrap();
+        }
+        for key in 2..8 {
+            slab.mark_for_reuse(key).unwrap();
+        }
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Slots(2)),
+            ReapProgress::Pending(2)
+        );
+        // live again before the reap continues
+        assert_eq!(slab.take_next_with(2).unwrap(), 2);
+        assert_eq!(
+            slab.reap_bounded(ReapBudget::Bytes(1024)),
+            ReapProgress::Done(2)
+        );
+        assert_eq!(slab.reap(), Some(0));
+        slab.mark_for_reuse(2).unwrap();
+        assert_eq!(slab.reap(), Some(2));
+    }
+
+    #[test]
+    fn reap_policy_signals() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(8, 2).unwrap();
+        slab.set_reap_policy(Some(ReapPolicy {
+            sustain_ops: 2,
+            ..ReapPolicy::default()
+        }));
+        for i in 0..8 {
+            slab.take_next_with(i).unwrap();
+        }
+        for key in (1..8).rev() {
+            slab.mark_for_reuse(key).unwrap();
+        }
+        assert!(slab.reap_due());
+        assert_eq!(slab.reap(), Some(6));
+        assert!(!slab.reap_due());
+    }
+
+    #[test]
+    fn reap_policy_reaps_incrementally() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(8, 2).unwrap();
+        slab.set_reap_policy(Some(ReapPolicy {
+            below_percent_of_peak: 50,
+            sustain_ops: 1,
+            sustain_for: None,
+            action: ReapAction::Bounded(ReapBudget::Slots(2)),
+        }));
+        for i in 0..8 {
+            slab.take_next_with(i).unwrap();
+        }
+        for key in (5..8).rev() {
+            slab.mark_for_reuse(key).unwrap();
+        }
+        assert!(!slab.reap_due());
+        // 4 of 8 is low and the first reap chunk runs right away
+        slab.mark_for_reuse(4).unwrap();
+        assert!(slab.reap_due());
+        // the next free continues until the live segment
+        slab.mark_for_reuse(3).unwrap();
+        assert!(!slab.reap_due());
+        assert_eq!(slab.reap(), Some(0));
+        assert_eq!(slab.remaining(), Some(5));
+    }
+
+    #[test]
+    fn memory_usage_follows_reap() {
+        let mut slab =
+            StableVecSlab::<u64>::with_fixed_capacity_and_segment_slots(128, 64).unwrap();
+        let full = slab.memory_usage();
+        assert!(full.allocated_bytes >= 128 * size_of::<u64>());
+        assert_eq!(full.reclaimable_bytes, full.allocated_bytes);
+        assert_eq!(full.live_bytes, 0);
+
+        slab.take_next_with(1).unwrap();
+        let one = slab.memory_usage();
+        assert_eq!(one.reclaimable_bytes, full.allocated_bytes / 2);
+        assert_eq!(one.live_bytes, size_of::<u64>() + one.overhead_per_slot);
+
+        assert_eq!(slab.reap(), Some(64));
+        let reaped = slab.memory_usage();
+        assert_eq!(reaped.allocated_bytes, full.allocated_bytes / 2);
+        assert_eq!(reaped.reclaimable_bytes, 0);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(3, 2).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        let a = slab.take_next_with(1).unwrap();
+        slab.take_next_with(2).unwrap();
+        assert_eq!(slab.take_next_with(3), Err(SlabbableError::AtCapacity(3)));
+        slab.take_reserved_with(reserved, 0).unwrap();
+        slab.mark_for_reuse(a).unwrap();
+
+        assert_eq!(
+            slab.stats(),
+            Some(SlabStats {
+                takes: 3,
+                frees: 1,
+                live: 2,
+                reserved: 0,
+                high_watermark: 3,
+                at_capacity: 1,
+                revolutions: 0,
+            })
+        );
+        slab.reset_stats();
+        assert_eq!(slab.stats().unwrap().high_watermark, 2);
+    }
+
+    #[test]
+    fn cancel_reserved_allows_reap() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(4, 2).unwrap();
+        slab.take_next_with(0).unwrap();
+        slab.take_next_with(1).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(reserved.id(), 2);
+        slab.cancel_reserved(reserved).unwrap();
+        assert_eq!(slab.reap(), Some(2));
+        assert_eq!(slab.reserve_next().unwrap().id(), 2);
+    }
+
+    #[test]
+    fn resize_moves_only_the_tail_segment() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(5, 2).unwrap();
+        let keys: Vec<usize> = (0..4).map(|i| slab.take_next_with(i).unwrap()).collect();
+        slab.mark_for_reuse(keys[3]).unwrap();
+        let ptr_0 = core::ptr::addr_of!(*slab.slot_get_ref(0).unwrap().unwrap());
+        assert_eq!(
+            slab.try_resize_fixed_capacity(8, ResizeMode::IfEmpty),
+            Err(SlabbableError::NotEmpty(3))
+        );
+        assert_eq!(
+            slab.try_resize_fixed_capacity(2, ResizeMode::NoAddressesHeld),
+            Err(SlabbableError::BelowOccupied(3))
+        );
+
+        slab.try_resize_fixed_capacity(3, ResizeMode::NoAddressesHeld)
+            .unwrap();
+        assert_eq!(slab.capacity(), 3);
+        assert_eq!(slab.slot_get_ref(2).unwrap(), Some(&2));
+        assert_eq!(slab.take_next_with(4), Err(SlabbableError::AtCapacity(3)));
+        assert_eq!(
+            ptr_0,
+            core::ptr::addr_of!(*slab.slot_get_ref(0).unwrap().unwrap())
+        );
+
+        slab.try_resize_fixed_capacity(7, ResizeMode::NoAddressesHeld)
+            .unwrap();
+        assert_eq!(slab.slot_get_ref(2).unwrap(), Some(&2));
+        let more: Vec<usize> = (0..4).map(|i| slab.take_next_with(i).unwrap()).collect();
+        assert_eq!(more, vec![3, 4, 5, 6]);
+        assert_eq!(slab.remaining(), None);
+    }
+
+    #[test]
+    fn resize_empty() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(4, 2).unwrap();
+        let key = slab.take_next_with(0).unwrap();
+        slab.mark_for_reuse(key).unwrap();
+        slab.reap();
+        slab.try_resize_fixed_capacity(1, ResizeMode::IfEmpty)
+            .unwrap();
+        assert_eq!(slab.take_next_with(1).unwrap(), 0);
+        assert_eq!(slab.take_next_with(2), Err(SlabbableError::AtCapacity(1)));
+    }
+
+    #[test]
+    fn compact_then_reap_tail() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(6, 2).unwrap();
+        let keys: Vec<usize> = (0..6).map(|i| slab.take_next_with(i).unwrap()).collect();
+        for key in [0, 2, 3] {
+            slab.mark_for_reuse(keys[key]).unwrap();
+        }
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(reserved.id(), 0);
+
+        // 4 is pinned so only 5 may move
+        let mut relocations = vec![];
+        let moved = slab.compact(|key, _| key != 4, |r, item| relocations.push((r, *item)));
+        assert_eq!(moved, Some(1));
+        let to_2 = Relocation {
+            old_key: 5,
+            new_key: 2,
+        };
+        assert_eq!(relocations, vec![(to_2, 5)]);
+        assert_eq!(slab.reap(), Some(0));
+
+        relocations.clear();
+        let moved = slab.compact(|_, _| true, |r, item| relocations.push((r, *item)));
+        assert_eq!(moved, Some(1));
+        assert_eq!(relocations[0].0.new_key, 3);
+        assert_eq!(slab.slot_get_ref(3).unwrap(), Some(&4));
+        assert_eq!(slab.slot_get_ref(4).unwrap(), None);
+        assert_eq!(slab.reap(), Some(2));
+
+        // the reservation stayed put
+        assert_eq!(slab.take_reserved_with(reserved, 0).unwrap(), 0);
+        assert_eq!(slab.take_next_with(6).unwrap(), 4);
+    }
+
+    #[test]
+    fn compact_frees_the_vacated_slots() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(6, 2).unwrap();
+        for i in 0..6 {
+            slab.take_next_with(i).unwrap();
+        }
+        assert!(slab.set_placement(Placement::Fifo));
+        slab.mark_for_reuse(1).unwrap();
+        slab.mark_for_reuse(2).unwrap();
+        slab.set_reap_policy(Some(ReapPolicy {
+            below_percent_of_peak: 100,
+            sustain_ops: 1,
+            sustain_for: None,
+            action: ReapAction::Bounded(ReapBudget::Slots(2)),
+        }));
+
+        assert_eq!(slab.compact(|_, _| true, |_, _| {}), Some(2));
+        // the tail segment emptied by the moves was reaped under the policy
+        assert_eq!(slab.memory_usage().reclaimable_bytes, 0);
+        assert_eq!(slab.reap(), Some(0));
+        // the moved into slots left the FIFO, the vacated ones joined it
+        assert_eq!(slab.take_next_with(6).unwrap(), 5);
+        assert_eq!(slab.take_next_with(7).unwrap(), 4);
+        assert_eq!(slab.take_next_with(8), Err(SlabbableError::AtCapacity(6)));
+    }
+
+    #[test]
+    fn placement_fifo_and_lifo() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(6, 2).unwrap();
+        assert_eq!(slab.placement(), Some(Placement::LowestFree));
+        for i in 0..6 {
+            slab.take_next_with(i).unwrap();
+        }
+        assert!(slab.set_placement(Placement::Fifo));
+        for key in [4, 1, 3] {
+            slab.mark_for_reuse(key).unwrap();
+        }
+        assert_eq!(slab.take_next_with(6).unwrap(), 4);
+
+        assert!(slab.set_placement(Placement::Lifo));
+        assert_eq!(slab.take_next_with(7).unwrap(), 3);
+        assert_eq!(slab.take_next_with(8).unwrap(), 1);
+        assert_eq!(slab.take_next_with(9), Err(SlabbableError::AtCapacity(6)));
+    }
+
+    #[test]
+    fn placement_lowest_free_lets_tail_reap() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(6, 2).unwrap();
+        for i in 0..6 {
+            slab.take_next_with(i).unwrap();
+        }
+        slab.set_placement(Placement::Lifo);
+        for key in [0, 5, 4] {
+            slab.mark_for_reuse(key).unwrap();
+        }
+        // back to lowest-free-first the ramp-down leaves the tail empty
+        slab.set_placement(Placement::LowestFree);
+        assert_eq!(slab.take_next_with(6).unwrap(), 0);
+        assert_eq!(slab.reap(), Some(2));
+    }
+
+    #[test]
+    fn quarantine_never_aliases_stale_key() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(4, 2).unwrap();
+        slab.set_quarantine(Some(Quarantine {
+            frees: 2,
+            ops: usize::MAX,
+        }));
+        let stale = slab.take_next_with(0).unwrap();
+        slab.mark_for_reuse(stale).unwrap();
+
+        // the late completion finds nothing to alias
+        assert_eq!(slab.slot_get_ref(stale).unwrap(), None);
+        assert_eq!(
+            slab.mark_for_reuse(stale),
+            Err(SlabbableError::InvalidIndex(stale))
+        );
+        let a = slab.take_next_with(1).unwrap();
+        let b = slab.take_next_with(2).unwrap();
+        assert!(a != stale && b != stale);
+        assert_eq!(slab.remaining(), Some(1));
+        slab.mark_for_reuse(a).unwrap();
+        assert_ne!(slab.take_next_with(3).unwrap(), stale);
+        slab.mark_for_reuse(b).unwrap();
+
+        // two other frees served the quarantine
+        assert_eq!(slab.take_next_with(4).unwrap(), stale);
+    }
+
+    #[test]
+    fn quarantine_by_ops_and_lifted() {
+        let mut slab = StableVecSlab::<u8>::with_fixed_capacity_and_segment_slots(2, 2).unwrap();
+        slab.set_quarantine(Some(Quarantine {
+            frees: usize::MAX,
+            ops: 3,
+        }));
+        let stale = slab.take_next_with(0).unwrap();
+        let reserved = slab.reserve_next().unwrap();
+        slab.mark_for_reuse(stale).unwrap();
+        slab.cancel_reserved(reserved).unwrap();
+        assert_eq!(slab.take_next_with(1), Err(SlabbableError::AtCapacity(2)));
+        // the third operation since the free releases it
+        assert_eq!(slab.take_next_with(1).unwrap(), stale);
+
+        slab.set_quarantine(None);
+        assert_eq!(slab.remaining(), Some(1));
     }
 }
@@ -10,14 +10,16 @@ struct SomeCStruct {
     yet_another: u32,
 }
 
-use slabbable::Slabbable;
+use slabbable::{SlabMemory, Slabbable};
+
+const FILL: usize = 10_024_000;
 
 fn fill_1m_basic<S, I: core::fmt::Debug>(slab: &mut S)
 where
     S: Slabbable<I, SomeCStruct>,
     <S as Slabbable<I, SomeCStruct>>::Error: std::fmt::Debug,
 {
-    for _z in 0..10_024_000 {
+    for _z in 0..FILL {
         let _slot = slab
             .take_next_with(SomeCStruct {
                 forever: 0,
@@ -94,52 +96,152 @@ fn print_mem_report(stage: String, rep: &MemReport) {
     println!("== {stage}\n - phys {phys} virt {virt}");
 }
 
-fn run_errand<S, I: core::fmt::Debug>(info: &'static str, slab: &mut S)
+fn delta(direction: &Direction) -> isize {
+    match direction {
+        Direction::Increased(v) => *v as isize,
+        Direction::Reduced(v) => -(*v as isize),
+        Direction::Same(_) => 0,
+    }
+}
+
+// RSS moves in pages and allocator chunks so only flag gross disagreement
+const RSS_TOLERANCE_PERCENT: isize = 20;
+
+// Both under- and over-reporting beyond the tolerance are a mismatch
+fn check_reported(stage: &str, reported: usize, measure: &str, measured: isize) -> bool {
+    let reported = reported as isize;
+    let diff = (measured - reported).abs();
+    let ok = diff * 100 <= reported.max(1) * RSS_TOLERANCE_PERCENT;
+    let verdict = if ok { "ok" } else { "MISMATCH" };
+    println!(
+        " - {stage}: reported {} vs {measure} {}{} .. {verdict}",
+        format_size(reported as u64, DECIMAL),
+        if measured < 0 { "-" } else { "+" },
+        format_size(measured.unsigned_abs() as u64, DECIMAL),
+    );
+    ok
+}
+
+fn print_slab_memory(mem: &SlabMemory) {
+    println!(
+        " - allocated {} live {} reclaimable {} overhead/slot {} B",
+        format_size(mem.allocated_bytes as u64, DECIMAL),
+        format_size(mem.live_bytes as u64, DECIMAL),
+        format_size(mem.reclaimable_bytes as u64, DECIMAL),
+        mem.overhead_per_slot,
+    );
+}
+
+fn run_errand<S, I: core::fmt::Debug, F>(info: &'static str, new_slab: F) -> bool
 where
     S: Slabbable<I, SomeCStruct>,
     <S as Slabbable<I, SomeCStruct>>::Error: std::fmt::Debug,
+    F: FnOnce() -> S,
 {
     let baseline = mem_take_snapshot();
+    let mut slab = new_slab();
     print_mem_report(
         format!("{} / initialized (baseline)", info),
         &mem_cmp_to(&baseline),
     );
-    fill_1m_basic(slab);
-    print_mem_report(
-        format!("{} / flled (over baseline)", info),
-        &mem_cmp_to(&baseline),
+    fill_1m_basic(&mut slab);
+    let filled = mem_cmp_to(&baseline);
+    print_mem_report(format!("{} / flled (over baseline)", info), &filled);
+    let mem = slab.memory_usage();
+    print_slab_memory(&mem);
+    // Pages never written to e.g. hash buckets beyond the sequential keys are
+    // not resident so the allocation is measured by the address space instead
+    let mut ok = check_reported(
+        "allocated",
+        mem.allocated_bytes,
+        "virt",
+        delta(&filled.virt),
     );
+
+    for slot in 0..FILL {
+        let _ = slab.mark_for_reuse(slot);
+    }
+    let mem = slab.memory_usage();
+    if mem.reclaimable_bytes > 0 {
+        let drained = mem_take_snapshot();
+        let reaped = slab.reap();
+        let after = mem_cmp_to(&drained);
+        print_mem_report(
+            format!("{} / drained and reaped {:?} slots", info, reaped),
+            &after,
+        );
+        ok &= check_reported(
+            "reclaimable",
+            mem.reclaimable_bytes,
+            "RSS",
+            -delta(&after.phys),
+        );
+    }
+    ok
 }
 
 #[cfg(feature = "slabbable-nohash-hasher")]
-fn nohash_hasher() {
-    let mut slab =
-        slabbable_nohash_hasher::NoHashSlab::<SomeCStruct>::with_fixed_capacity(10_024_000)
-            .unwrap();
-    run_errand("nohash-hasher", &mut slab);
+fn nohash_hasher() -> bool {
+    run_errand("nohash-hasher", || {
+        slabbable_nohash_hasher::NoHashSlab::<SomeCStruct>::with_fixed_capacity(FILL).unwrap()
+    })
+}
+
+#[cfg(feature = "slabbable-hash")]
+fn hash() -> bool {
+    run_errand("hash", || {
+        slabbable_hash::HashSlab::<SomeCStruct>::with_fixed_capacity(FILL).unwrap()
+    })
 }
 
 #[cfg(feature = "slabbable-slab")]
-fn slab() {
-    let mut slab =
-        slabbable_slab::SlabSlab::<SomeCStruct>::with_fixed_capacity(10_024_000).unwrap();
-    run_errand("slab", &mut slab);
+fn slab() -> bool {
+    run_errand("slab", || {
+        slabbable_slab::SlabSlab::<SomeCStruct>::with_fixed_capacity(FILL).unwrap()
+    })
 }
 
+// Segments large enough for the allocator to hand them back to the OS upon reap
 #[cfg(feature = "slabbable-stablevec")]
-fn stablevec() {
-    let mut slab =
-        slabbable_stablevec::StableVecSlab::<SomeCStruct>::with_fixed_capacity(10_024_000).unwrap();
-    run_errand("StableVec", &mut slab);
+const SEGMENT_BYTES: usize = 1 << 20;
+
+#[cfg(feature = "slabbable-stablevec")]
+fn stablevec() -> bool {
+    run_errand("StableVec", || {
+        let segment_slots = SEGMENT_BYTES / size_of::<SomeCStruct>();
+        slabbable_stablevec::StableVecSlab::<SomeCStruct>::with_fixed_capacity_and_segment_slots(
+            FILL,
+            segment_slots,
+        )
+        .unwrap()
+    })
 }
 
 fn main() {
+    #[allow(unused_mut)]
+    let mut ok = true;
+
     #[cfg(feature = "slabbable-nohash-hasher")]
-    nohash_hasher();
+    {
+        ok &= nohash_hasher();
+    }
+
+    #[cfg(feature = "slabbable-hash")]
+    {
+        ok &= hash();
+    }
 
     #[cfg(feature = "slabbable-slab")]
-    slab();
+    {
+        ok &= slab();
+    }
 
     #[cfg(feature = "slabbable-stablevec")]
-    stablevec();
+    {
+        ok &= stablevec();
+    }
+
+    if !ok {
+        std::process::exit(1);
+    }
 }
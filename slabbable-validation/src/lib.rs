@@ -10,6 +10,7 @@
 #![doc = include_str!("../README.md")]
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used, dead_code)]
 mod test {
     use slabbable::Slabbable;
 
@@ -0,0 +1,385 @@
+//! Slabbable slot age tracking
+
+use core::cell::Cell;
+use core::time::Duration;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Source of the timestamps AgedSlab stamps the slots with.
+pub trait SlabClock {
+    /// The current instant
+    fn now(&self) -> Instant;
+}
+
+/// Monotonic system clock (default)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl SlabClock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when advanced, for faking time in tests.
+/// Clones share the same time so one can be kept to drive the slab's.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    base: Instant,
+    offset: Rc<Cell<Duration>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self {
+            base: Instant::now(),
+            offset: Rc::new(Cell::new(Duration::ZERO)),
+        }
+    }
+}
+
+impl ManualClock {
+    /// Move the time forward by the given duration
+    pub fn advance(&self, by: Duration) {
+        self.offset.set(self.offset.get() + by);
+    }
+}
+
+impl SlabClock for ManualClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        self.base + self.offset.get()
+    }
+}
+
+/// Log-scale histogram of slot lifetimes in nanoseconds. Bucket 0 holds zero
+/// lifetimes and bucket i holds lifetimes within [2^(i-1), 2^i) nanoseconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LifetimeHistogram {
+    buckets: [u64; 65],
+}
+
+impl Default for LifetimeHistogram {
+    fn default() -> Self {
+        Self { buckets: [0; 65] }
+    }
+}
+
+impl LifetimeHistogram {
+    #[inline]
+    fn _bucket(lifetime: Duration) -> usize {
+        let nanos = u64::try_from(lifetime.as_nanos()).unwrap_or(u64::MAX);
+        (u64::BITS - nanos.leading_zeros()) as usize
+    }
+    /// Record a slot lifetime
+    #[inline]
+    pub fn record(&mut self, lifetime: Duration) {
+        self.buckets[Self::_bucket(lifetime)] += 1;
+    }
+    /// Lifetimes recorded
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+    /// Exclusive upper bound of the given bucket
+    pub fn bucket_upper_bound(bucket: usize) -> Duration {
+        match bucket {
+            0 => Duration::from_nanos(1),
+            64.. => Duration::MAX,
+            _ => Duration::from_nanos(1 << bucket),
+        }
+    }
+    /// Non-empty buckets as (exclusive upper bound, count) in ascending order
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(bucket, count)| (Self::bucket_upper_bound(bucket), *count))
+    }
+    /// Zero the histogram
+    pub fn reset(&mut self) {
+        self.buckets = [0; 65];
+    }
+}
+
+/// Wraps any Slabbable timestamping the slots upon take from the clock C so the
+/// oldest live slots, e.g. in-flight operations that never complete, can be
+/// listed and the lifetimes are recorded into a histogram upon mark_for_reuse.
+#[derive(Debug)]
+pub struct AgedSlab<S, C = SystemClock> {
+    inner: S,
+    clock: C,
+    taken_at: HashMap<usize, Instant>,
+    lifetimes: LifetimeHistogram,
+}
+
+impl<S, C: SlabClock> AgedSlab<S, C> {
+    /// Track the slots of the given slab with the given clock
+    pub fn with_clock(inner: S, clock: C) -> Self {
+        Self {
+            inner,
+            clock,
+            taken_at: HashMap::new(),
+            lifetimes: LifetimeHistogram::default(),
+        }
+    }
+    /// How long the given slot has been taken for
+    pub fn age_of(&self, slot: usize) -> Option<Duration> {
+        let now = self.clock.now();
+        self.taken_at
+            .get(&slot)
+            .map(|at| now.saturating_duration_since(*at))
+    }
+    /// Up to n oldest live slots as (slot, age) oldest first
+    pub fn oldest(&self, n: usize) -> Vec<(usize, Duration)> {
+        let mut by_age: Vec<(usize, Instant)> = self
+            .taken_at
+            .iter()
+            .map(|(slot, at)| (*slot, *at))
+            .collect();
+        if n < by_age.len() {
+            by_age.select_nth_unstable_by_key(n, |(slot, at)| (*at, *slot));
+            by_age.truncate(n);
+        }
+        by_age.sort_unstable_by_key(|(slot, at)| (*at, *slot));
+        let now = self.clock.now();
+        by_age
+            .into_iter()
+            .map(|(slot, at)| (slot, now.saturating_duration_since(at)))
+            .collect()
+    }
+    /// Histogram of the lifetimes of the slots marked for re-use
+    pub fn lifetimes(&self) -> &LifetimeHistogram {
+        &self.lifetimes
+    }
+    /// Zero the lifetime histogram
+    pub fn reset_lifetimes(&mut self) {
+        self.lifetimes.reset();
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// The clock the slots are stamped from
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+}
+
+impl<S, T, C> Slabbable<AgedSlab<S, C>, T> for AgedSlab<S, C>
+where
+    S: Slabbable<S, T>,
+    C: SlabClock + Default,
+{
+    type Error = S::Error;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::with_clock(S::with_fixed_capacity(cap)?, C::default()))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self.inner.reserve_next()
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        let id = self.inner.take_reserved_with(slot, with)?;
+        self.taken_at.insert(id, self.clock.now());
+        Ok(id)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error>
+    where
+        Self::Error: From<SlabbableError>,
+    {
+        self.inner.cancel_reserved(slot)
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        let id = self.inner.take_next_with(with)?;
+        self.taken_at.insert(id, self.clock.now());
+        Ok(id)
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        let item = self.inner.mark_for_reuse(slot)?;
+        if let Some(at) = self.taken_at.remove(&slot) {
+            let lifetime = self.clock.now().saturating_duration_since(at);
+            self.lifetimes.record(lifetime);
+        }
+        Ok(item)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error>
+    where
+        Self::Error: From<SlabbableError>,
+    {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - the timestamps follow the relocated items.
+    fn compact<M, R>(&mut self, movable: M, mut relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        let taken_at = &mut self.taken_at;
+        self.inner.compact(movable, |relocation, item| {
+            if let Some(at) = taken_at.remove(&relocation.old_key) {
+                taken_at.insert(relocation.new_key, at);
+            }
+            relocated(relocation, item)
+        })
+    }
+    /// See trait - the timestamps are not accounted.
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn oldest_and_lifetimes() {
+        let clock = ManualClock::default();
+        let mut slab = AgedSlab::with_clock(
+            TestableSlab::<u8>::with_fixed_capacity(4).unwrap(),
+            clock.clone(),
+        );
+        let a = slab.take_next_with(0).unwrap();
+        clock.advance(Duration::from_millis(10));
+        let reserved = slab.reserve_next().unwrap();
+        let b = slab.take_next_with(1).unwrap();
+        clock.advance(Duration::from_millis(10));
+        let c = slab.take_reserved_with(reserved, 2).unwrap();
+        clock.advance(Duration::from_millis(5));
+
+        assert_eq!(
+            slab.oldest(2),
+            vec![
+                (a, Duration::from_millis(25)),
+                (b, Duration::from_millis(15)),
+            ]
+        );
+        assert_eq!(slab.oldest(10).len(), 3);
+        assert_eq!(slab.age_of(c), Some(Duration::from_millis(5)));
+
+        slab.mark_for_reuse(a).unwrap();
+        slab.mark_for_reuse(c).unwrap();
+        assert_eq!(slab.age_of(a), None);
+        assert_eq!(slab.oldest(10), vec![(b, Duration::from_millis(15))]);
+
+        // 25ms and 5ms land in the buckets bounded by the next powers of two
+        let buckets: Vec<(Duration, u64)> = slab.lifetimes().buckets().collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (Duration::from_nanos(1 << 23), 1),
+                (Duration::from_nanos(1 << 25), 1),
+            ]
+        );
+        assert_eq!(slab.lifetimes().count(), 2);
+        slab.reset_lifetimes();
+        assert_eq!(slab.lifetimes().count(), 0);
+    }
+
+    #[test]
+    fn histogram_bounds() {
+        let mut histogram = LifetimeHistogram::default();
+        histogram.record(Duration::ZERO);
+        histogram.record(Duration::from_nanos(1));
+        histogram.record(Duration::MAX);
+        let buckets: Vec<(Duration, u64)> = histogram.buckets().collect();
+        assert_eq!(
+            buckets,
+            vec![
+                (Duration::from_nanos(1), 1),
+                (Duration::from_nanos(2), 1),
+                (Duration::MAX, 1),
+            ]
+        );
+    }
+}
@@ -0,0 +1,385 @@
+//! Slabbable async waiting for capacity
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::{ReservedSlot, Slabbable, SlabbableError};
+
+#[derive(Debug)]
+struct Waiter {
+    ticket: u64,
+    waker: Option<Waker>,
+    // slot reserved on behalf of the waiter once capacity was freed
+    granted: Option<ReservedSlot>,
+}
+
+#[derive(Debug)]
+struct Shared<S> {
+    slab: S,
+    waiters: VecDeque<Waiter>,
+    next_ticket: u64,
+}
+
+impl<S> Shared<S> {
+    // Reserve freed capacity for the waiters in FIFO order returning who to wake.
+    fn _grant<T>(&mut self) -> Vec<Waker>
+    where
+        S: Slabbable<S, T>,
+    {
+        let mut wake = vec![];
+        for waiter in self.waiters.iter_mut().filter(|w| w.granted.is_none()) {
+            match self.slab.reserve_next() {
+                Ok(slot) => waiter.granted = Some(slot),
+                Err(_) => break,
+            }
+            if let Some(waker) = waiter.waker.take() {
+                wake.push(waker);
+            }
+        }
+        wake
+    }
+    // Pass the reservation of a dropped waiter on or release it.
+    fn _regrant<T>(&mut self, slot: ReservedSlot) -> Option<Waker>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        match self.waiters.iter_mut().find(|w| w.granted.is_none()) {
+            Some(waiter) => {
+                waiter.granted = Some(slot);
+                waiter.waker.take()
+            }
+            None => {
+                // nothing to report to from drop
+                let _ = self.slab.cancel_reserved(slot);
+                None
+            }
+        }
+    }
+}
+
+/// Single-threaded shared handle to a Slabbable whose reserve_next_async()
+/// waits for capacity instead of failing with AtCapacity. Capacity freed through
+/// the handle is reserved for the waiters in FIFO order before it is woken so
+/// the direct reserve_next() / take_next_with() can't barge ahead of them.
+///
+/// Driven by core::task::Waker only so it works with any executor. The slots
+/// must be freed through the handle, or with_slab_mut(), to wake the waiters.
+#[derive(Debug)]
+pub struct AsyncSlab<S> {
+    shared: Rc<RefCell<Shared<S>>>,
+}
+
+impl<S> Clone for AsyncSlab<S> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S> AsyncSlab<S> {
+    /// Share the given slab
+    pub fn new(slab: S) -> Self {
+        Self {
+            shared: Rc::new(RefCell::new(Shared {
+                slab,
+                waiters: VecDeque::new(),
+                next_ticket: 0,
+            })),
+        }
+    }
+    /// Number of reserve_next_async() futures waiting
+    pub fn waiting(&self) -> usize {
+        self.shared.borrow().waiters.len()
+    }
+    /// Access the slab e.g. for slot_get_ref()
+    ///
+    /// # Panics
+    ///
+    /// The slab stays borrowed while f runs so calling the handle mutably from
+    /// within f e.g. through a clone panics.
+    pub fn with_slab<R>(&self, f: impl FnOnce(&S) -> R) -> R {
+        f(&self.shared.borrow().slab)
+    }
+    /// Access the slab mutably handing any capacity freed within to the waiters
+    ///
+    /// # Panics
+    ///
+    /// The slab stays borrowed while f runs so calling the handle from within f
+    /// e.g. through a clone panics.
+    pub fn with_slab_mut<T, R>(&self, f: impl FnOnce(&mut S) -> R) -> R
+    where
+        S: Slabbable<S, T>,
+    {
+        let (ret, wake) = {
+            let mut shared = self.shared.borrow_mut();
+            let ret = f(&mut shared.slab);
+            (ret, shared._grant())
+        };
+        wake.into_iter().for_each(Waker::wake);
+        ret
+    }
+    /// Reserve the next free slot waiting in FIFO order for one to be freed if
+    /// at capacity. Dropping the future before it resolves gives up its place
+    /// and any slot reserved for it is passed to the next waiter.
+    pub fn reserve_next_async<T>(&self) -> ReserveNext<S, T>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        ReserveNext {
+            shared: self.shared.clone(),
+            ticket: None,
+            _item: PhantomData,
+        }
+    }
+    /// Reserve the next free slot without waiting
+    pub fn reserve_next<T>(&self) -> Result<ReservedSlot, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self.shared.borrow_mut().slab.reserve_next()
+    }
+    /// Take the previously reserved slot
+    pub fn take_reserved_with<T>(
+        &self,
+        slot: ReservedSlot,
+        with: T,
+    ) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self.shared.borrow_mut().slab.take_reserved_with(slot, with)
+    }
+    /// Take the next free slot without waiting
+    pub fn take_next_with<T>(&self, with: T) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self.shared.borrow_mut().slab.take_next_with(with)
+    }
+    /// Release the reserved slot waking the next waiter
+    pub fn cancel_reserved<T>(&self, slot: ReservedSlot) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self.with_slab_mut(|slab| slab.cancel_reserved(slot))
+    }
+    /// Mark the slot for re-use waking the next waiter
+    pub fn mark_for_reuse<T>(&self, slot: usize) -> Result<T, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self.with_slab_mut(|slab| slab.mark_for_reuse(slot))
+    }
+}
+
+/// Future of AsyncSlab::reserve_next_async()
+#[derive(Debug)]
+pub struct ReserveNext<S, T>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    shared: Rc<RefCell<Shared<S>>>,
+    // place in the queue once waiting
+    ticket: Option<u64>,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<S, T> Future for ReserveNext<S, T>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Output = Result<ReservedSlot, SlabbableError>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut shared = this.shared.borrow_mut();
+        let ticket = match this.ticket {
+            Some(ticket) => ticket,
+            None => {
+                if shared.waiters.is_empty() {
+                    match shared.slab.reserve_next() {
+                        Err(SlabbableError::AtCapacity(_)) => {}
+                        ready => return Poll::Ready(ready),
+                    }
+                }
+                let ticket = shared.next_ticket;
+                shared.next_ticket += 1;
+                shared.waiters.push_back(Waiter {
+                    ticket,
+                    waker: Some(cx.waker().clone()),
+                    granted: None,
+                });
+                this.ticket = Some(ticket);
+                // capacity may be left over from a waiter that gave up
+                let wake = shared._grant();
+                drop(shared);
+                wake.into_iter().for_each(Waker::wake);
+                return Poll::Pending;
+            }
+        };
+        let pos = match shared.waiters.iter().position(|w| w.ticket == ticket) {
+            Some(pos) => pos,
+            None => return Poll::Ready(Err(SlabbableError::Bug("Waiter lost its place"))),
+        };
+        if shared.waiters[pos].granted.is_none() {
+            shared.waiters[pos].waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        this.ticket = None;
+        match shared.waiters.remove(pos).and_then(|w| w.granted) {
+            Some(slot) => Poll::Ready(Ok(slot)),
+            None => Poll::Ready(Err(SlabbableError::Bug("Granted slot vanished"))),
+        }
+    }
+}
+
+impl<S, T> Drop for ReserveNext<S, T>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    fn drop(&mut self) {
+        let ticket = match self.ticket {
+            Some(ticket) => ticket,
+            None => return,
+        };
+        let wake = {
+            let mut shared = self.shared.borrow_mut();
+            let pos = match shared.waiters.iter().position(|w| w.ticket == ticket) {
+                Some(pos) => pos,
+                None => return,
+            };
+            match shared.waiters.remove(pos).and_then(|w| w.granted) {
+                Some(slot) => shared._regrant(slot),
+                None => None,
+            }
+        };
+        if let Some(waker) = wake {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    // Minimal local executor: poll by hand and count the wakes
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    struct Task<F> {
+        fut: F,
+        wakes: Arc<CountingWaker>,
+    }
+
+    impl<F: Future + Unpin> Task<F> {
+        fn new(fut: F) -> Self {
+            Self {
+                fut,
+                wakes: Arc::default(),
+            }
+        }
+        fn poll(&mut self) -> Poll<F::Output> {
+            let waker = Waker::from(self.wakes.clone());
+            Pin::new(&mut self.fut).poll(&mut Context::from_waker(&waker))
+        }
+        fn woken(&self) -> usize {
+            self.wakes.0.load(Ordering::Relaxed)
+        }
+    }
+
+    fn full_slab() -> (AsyncSlab<TestableSlab<u8>>, usize, usize) {
+        let slab = AsyncSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        let a = slab.take_next_with(0).unwrap();
+        let b = slab.take_next_with(1).unwrap();
+        (slab, a, b)
+    }
+
+    #[test]
+    #[should_panic(expected = "borrowed")]
+    fn reentry_from_with_slab_mut_panics() {
+        let (slab, a, _) = full_slab();
+        let handle = slab.clone();
+        slab.with_slab_mut(|inner| {
+            inner.mark_for_reuse(a).unwrap();
+            let _ = handle.take_next_with(2);
+        });
+    }
+
+    #[test]
+    fn ready_with_capacity() {
+        let slab = AsyncSlab::new(TestableSlab::<u8>::with_fixed_capacity(1).unwrap());
+        let mut task = Task::new(slab.reserve_next_async());
+        let reserved = match task.poll() {
+            Poll::Ready(Ok(reserved)) => reserved,
+            other => panic!("{:?}", other),
+        };
+        slab.take_reserved_with(reserved, 1).unwrap();
+        assert_eq!(slab.waiting(), 0);
+    }
+
+    #[test]
+    fn waiters_served_fifo() {
+        let (slab, a, b) = full_slab();
+        let mut first = Task::new(slab.reserve_next_async());
+        let mut second = Task::new(slab.reserve_next_async());
+        assert!(second.poll().is_pending());
+        assert!(first.poll().is_pending());
+        assert_eq!(slab.waiting(), 2);
+
+        slab.mark_for_reuse(a).unwrap();
+        // second polled first so it's first in the queue
+        assert_eq!((second.woken(), first.woken()), (1, 0));
+        assert_eq!(slab.take_next_with(9), Err(SlabbableError::AtCapacity(2)));
+        assert!(first.poll().is_pending());
+        let reserved = match second.poll() {
+            Poll::Ready(Ok(reserved)) => reserved,
+            other => panic!("{:?}", other),
+        };
+        slab.take_reserved_with(reserved, 2).unwrap();
+
+        slab.mark_for_reuse(b).unwrap();
+        assert_eq!(first.woken(), 1);
+        assert!(first.poll().is_ready());
+        assert_eq!(slab.waiting(), 0);
+    }
+
+    #[test]
+    fn dropped_waiter_passes_slot_on() {
+        let (slab, a, b) = full_slab();
+        let mut first = Task::new(slab.reserve_next_async());
+        let mut second = Task::new(slab.reserve_next_async());
+        assert!(first.poll().is_pending());
+        assert!(second.poll().is_pending());
+
+        slab.mark_for_reuse(a).unwrap();
+        assert_eq!(first.woken(), 1);
+        drop(first);
+        assert_eq!(second.woken(), 1);
+        assert!(second.poll().is_ready());
+
+        // dropped with nobody waiting releases the reservation
+        let mut third = Task::new(slab.reserve_next_async());
+        assert!(third.poll().is_pending());
+        slab.mark_for_reuse(b).unwrap();
+        drop(third);
+        assert_eq!(slab.waiting(), 0);
+        slab.take_next_with(3).unwrap();
+    }
+}
@@ -0,0 +1,447 @@
+//! Slabbable per-slot deadlines
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::Instant;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// What expire() does with a taken slot whose deadline has passed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExpireAction {
+    /// Mark the slot for re-use dropping the item
+    Free,
+    /// Keep the slot and clear its deadline
+    Keep,
+    /// Keep the slot with a new deadline
+    Extend(Instant),
+}
+
+/// Wraps any Slabbable with an optional deadline per slot, set at take time or
+/// later, so timeouts don't need a separate structure mirroring the keys.
+/// Deadlines are indexed in order so expire() visits only the passed ones.
+///
+/// A reservation expired by expire() is cancelled and its ReservedSlot must
+/// not be taken afterwards as the slot may have been reserved again.
+#[derive(Debug)]
+pub struct DeadlineSlab<S> {
+    inner: S,
+    // ordered by deadline for the sweep
+    index: BTreeSet<(Instant, usize)>,
+    deadlines: HashMap<usize, Instant>,
+    // reservations made through us not yet taken
+    reserved: HashSet<usize>,
+}
+
+impl<S> DeadlineSlab<S> {
+    /// Track the deadlines of the slots of the given slab
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            index: BTreeSet::new(),
+            deadlines: HashMap::new(),
+            reserved: HashSet::new(),
+        }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// Deadline of the given slot if any
+    pub fn deadline_of(&self, slot: usize) -> Option<Instant> {
+        self.deadlines.get(&slot).copied()
+    }
+    /// The earliest deadline e.g. for arming a timer until the next expire()
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.index.first().map(|(deadline, _)| *deadline)
+    }
+    /// Remove the deadline of the given slot returning it
+    pub fn clear_deadline(&mut self, slot: usize) -> Option<Instant> {
+        let deadline = self.deadlines.remove(&slot)?;
+        self.index.remove(&(deadline, slot));
+        Some(deadline)
+    }
+    fn _insert_deadline(&mut self, slot: usize, deadline: Instant) {
+        if let Some(prev) = self.deadlines.insert(slot, deadline) {
+            self.index.remove(&(prev, slot));
+        }
+        self.index.insert((deadline, slot));
+    }
+    /// Set or replace the deadline of a taken or reserved slot
+    pub fn set_deadline<T>(&mut self, slot: usize, deadline: Instant) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        if !self.reserved.contains(&slot) && self.inner.slot_get_ref(slot)?.is_none() {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        self._insert_deadline(slot, deadline);
+        Ok(())
+    }
+    /// Reserve the next free slot expiring at the given deadline unless taken
+    pub fn reserve_next_with_deadline<T>(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<ReservedSlot, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        let slot = self.reserve_next()?;
+        self._insert_deadline(slot.id(), deadline);
+        Ok(slot)
+    }
+    /// Take the next free slot with the given deadline
+    pub fn take_next_with_deadline<T>(
+        &mut self,
+        with: T,
+        deadline: Instant,
+    ) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        let slot = self.take_next_with(with)?;
+        self._insert_deadline(slot, deadline);
+        Ok(slot)
+    }
+    /// Visit the slots whose deadline is at or before now in deadline order.
+    /// Reservations never taken are cancelled and the taken slots are handed to
+    /// the closure deciding the ExpireAction. Returns the slots reclaimed.
+    pub fn expire<T, F>(&mut self, now: Instant, mut f: F) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+        F: FnMut(usize, &mut T) -> ExpireAction,
+    {
+        let mut reclaimed = 0;
+        // Extended to a deadline passed already waits for the next call
+        let mut deferred = vec![];
+        let mut swept = Ok(());
+        while let Some((deadline, slot)) = self.index.first().copied() {
+            if deadline > now {
+                break;
+            }
+            match self._expire_slot(slot, now, &mut f, &mut deferred) {
+                Ok(true) => reclaimed += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    swept = Err(e);
+                    break;
+                }
+            }
+        }
+        for (slot, later) in deferred {
+            self._insert_deadline(slot, later);
+        }
+        swept.map(|_| reclaimed)
+    }
+    // The deadline is only removed once the slot is dealt with so a failing
+    // slot stays indexed. Returns whether the slot was reclaimed.
+    fn _expire_slot<T, F>(
+        &mut self,
+        slot: usize,
+        now: Instant,
+        f: &mut F,
+        deferred: &mut Vec<(usize, Instant)>,
+    ) -> Result<bool, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+        F: FnMut(usize, &mut T) -> ExpireAction,
+    {
+        if self.reserved.contains(&slot) {
+            self.inner.cancel_reserved(ReservedSlot::issue(slot))?;
+            self.reserved.remove(&slot);
+            self.clear_deadline(slot);
+            return Ok(true);
+        }
+        let action = match self.inner.slot_get_mut(slot)? {
+            Some(item) => f(slot, item),
+            None => return Err(SlabbableError::Bug("Deadline of a vacant slot")),
+        };
+        match action {
+            ExpireAction::Free => {
+                self.inner.mark_for_reuse(slot)?;
+                self.clear_deadline(slot);
+                return Ok(true);
+            }
+            ExpireAction::Keep => {
+                self.clear_deadline(slot);
+            }
+            ExpireAction::Extend(later) if later > now => self._insert_deadline(slot, later),
+            ExpireAction::Extend(later) => {
+                self.clear_deadline(slot);
+                deferred.push((slot, later));
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<S, T> Slabbable<DeadlineSlab<S>, T> for DeadlineSlab<S>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        let slot = self.inner.reserve_next()?;
+        self.reserved.insert(slot.id());
+        Ok(slot)
+    }
+    /// See trait - the deadline of the reservation carries over.
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        let id = slot.id();
+        if !self.reserved.contains(&id) {
+            return Err(SlabbableError::InvalidTransition(id));
+        }
+        let taken = self.inner.take_reserved_with(slot, with)?;
+        self.reserved.remove(&id);
+        Ok(taken)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        let id = slot.id();
+        if !self.reserved.contains(&id) {
+            return Err(SlabbableError::InvalidTransition(id));
+        }
+        self.inner.cancel_reserved(slot)?;
+        self.reserved.remove(&id);
+        self.clear_deadline(id);
+        Ok(())
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_next_with(with)
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        let item = self.inner.mark_for_reuse(slot)?;
+        self.clear_deadline(slot);
+        Ok(item)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - the deadlines follow the relocated items.
+    fn compact<M, R>(&mut self, movable: M, mut relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        let (index, deadlines) = (&mut self.index, &mut self.deadlines);
+        self.inner.compact(movable, |relocation, item| {
+            let Relocation { old_key, new_key } = relocation;
+            if let Some(deadline) = deadlines.remove(&old_key) {
+                index.remove(&(deadline, old_key));
+                index.insert((deadline, new_key));
+                deadlines.insert(new_key, deadline);
+            }
+            relocated(relocation, item)
+        })
+    }
+    /// See trait - the deadline index is not accounted.
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+    use core::time::Duration;
+
+    #[test]
+    fn expire_passed_deadlines() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut slab = DeadlineSlab::new(TestableSlab::<u8>::with_fixed_capacity(8).unwrap());
+
+        let freed = slab.take_next_with_deadline(1, at(10)).unwrap();
+        let kept = slab.take_next_with_deadline(2, at(20)).unwrap();
+        let extended = slab.take_next_with(3).unwrap();
+        slab.set_deadline(extended, at(5)).unwrap();
+        let reserved = slab.reserve_next_with_deadline(at(15)).unwrap();
+        let later = slab.take_next_with_deadline(4, at(100)).unwrap();
+        let never = slab.take_next_with(5).unwrap();
+        assert_eq!(
+            slab.set_deadline(99, at(1)),
+            Err(SlabbableError::InvalidIndex(99))
+        );
+        assert_eq!(slab.next_deadline(), Some(at(5)));
+
+        let mut visited = vec![];
+        let reclaimed = slab
+            .expire(at(30), |slot, item| {
+                visited.push((slot, *item));
+                match *item {
+                    1 => ExpireAction::Free,
+                    2 => ExpireAction::Keep,
+                    _ => ExpireAction::Extend(at(50)),
+                }
+            })
+            .unwrap();
+        assert_eq!(visited, vec![(extended, 3), (freed, 1), (kept, 2)]);
+        // the freed slot and the reservation
+        assert_eq!(reclaimed, 2);
+        assert_eq!(slab.slot_get_ref(freed).unwrap(), None);
+        let reserved_id = reserved.id();
+        assert_eq!(
+            slab.take_reserved_with(reserved, 0),
+            Err(SlabbableError::InvalidTransition(reserved_id))
+        );
+        assert_eq!(slab.deadline_of(kept), None);
+        assert_eq!(slab.deadline_of(extended), Some(at(50)));
+        assert_eq!(slab.deadline_of(later), Some(at(100)));
+        assert_eq!(slab.deadline_of(never), None);
+
+        slab.mark_for_reuse(later).unwrap();
+        assert_eq!(slab.deadline_of(later), None);
+        assert_eq!(slab.next_deadline(), Some(at(50)));
+    }
+
+    #[test]
+    fn failed_sweep_keeps_the_deadlines() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut slab = DeadlineSlab::new(TestableSlab::<u8>::with_fixed_capacity(4).unwrap());
+        let deferred = slab.take_next_with_deadline(1, at(10)).unwrap();
+        let vanished = slab.take_next_with_deadline(2, at(20)).unwrap();
+        let pending = slab.take_next_with_deadline(3, at(30)).unwrap();
+        // freed behind our back
+        slab.inner.mark_for_reuse(vanished).unwrap();
+
+        assert_eq!(
+            slab.expire(at(30), |_, _| ExpireAction::Extend(at(5))),
+            Err(SlabbableError::Bug("Deadline of a vacant slot"))
+        );
+        assert_eq!(slab.deadline_of(deferred), Some(at(5)));
+        assert_eq!(slab.deadline_of(vanished), Some(at(20)));
+        assert_eq!(slab.deadline_of(pending), Some(at(30)));
+        assert_eq!(slab.next_deadline(), Some(at(5)));
+    }
+
+    #[test]
+    fn expired_reservation_cant_touch_reissued_slot() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut slab = DeadlineSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        // every ID issued is 0 so the expired reservation's index is reused
+        assert!(slab.set_key_limit(1));
+
+        let stale = slab.reserve_next_with_deadline(at(10)).unwrap();
+        let stale_id = stale.id();
+        assert_eq!(slab.expire(at(20), |_, _| ExpireAction::Free), Ok(1));
+        let reissued = slab.take_next_with(7).unwrap();
+        assert_eq!(reissued, stale_id);
+
+        assert_eq!(
+            slab.take_reserved_with(ReservedSlot::issue(stale_id), 0),
+            Err(SlabbableError::InvalidTransition(stale_id))
+        );
+        assert_eq!(
+            slab.cancel_reserved(stale),
+            Err(SlabbableError::InvalidTransition(stale_id))
+        );
+        assert_eq!(slab.slot_get_ref(reissued).unwrap(), Some(&7));
+    }
+
+    #[test]
+    fn extend_into_past_waits_for_next_sweep() {
+        let start = Instant::now();
+        let mut slab = DeadlineSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        let slot = slab.take_next_with_deadline(1, start).unwrap();
+        let mut visits = 0;
+        for _sweep in 0..2 {
+            let reclaimed = slab
+                .expire(start, |_, _| {
+                    visits += 1;
+                    ExpireAction::Extend(start)
+                })
+                .unwrap();
+            assert_eq!(reclaimed, 0);
+        }
+        assert_eq!(visits, 2);
+        assert_eq!(slab.deadline_of(slot), Some(start));
+    }
+}
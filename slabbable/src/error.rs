@@ -13,6 +13,20 @@ pub enum SlabbableError {
     AtCapacity(usize),
     /// Invalid index referred in the request
     InvalidIndex(usize),
+    /// The partition given is at its quota although the slab may not be
+    QuotaExceeded(usize),
+    /// Slots are held so the items can't be moved for resize
+    NotEmpty(usize),
+    /// The live keys would not fit the requested capacity, at least this is needed
+    BelowOccupied(usize),
+    /// The slot is submitted to the kernel which may still reference it
+    KernelOwned(usize),
+    /// The slot is not in a state the requested transition starts from
+    InvalidTransition(usize),
+    /// The slot is pinned by outstanding references
+    Pinned(usize),
+    /// The implementation does not support the requested operation
+    Unsupported,
     /// This is a bug and should not happen.
     Bug(&'static str),
 }
@@ -22,6 +36,13 @@ impl Display for SlabbableError {
         match self {
             Self::AtCapacity(s) => write!(f, "At maximum fixed capacity: {}", s),
             Self::InvalidIndex(s) => write!(f, "Invalid slot: {}", s),
+            Self::QuotaExceeded(s) => write!(f, "Partition at its quota: {}", s),
+            Self::NotEmpty(s) => write!(f, "Can't resize with slots held: {}", s),
+            Self::BelowOccupied(s) => write!(f, "Capacity needed by the live keys: {}", s),
+            Self::KernelOwned(s) => write!(f, "Slot owned by the kernel: {}", s),
+            Self::InvalidTransition(s) => write!(f, "Invalid slot state transition: {}", s),
+            Self::Pinned(s) => write!(f, "Slot pinned by outstanding references: {}", s),
+            Self::Unsupported => write!(f, "Not supported by the implementation"),
             Self::Bug(s) => write!(f, "BUG: Please report this bug: {}", s),
         }
     }
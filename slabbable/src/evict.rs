@@ -0,0 +1,451 @@
+//! Slabbable eviction at capacity
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// How take_next_or_evict_with() selects the victim at capacity.
+/// Only taken slots are candidates, reservations are never evicted.
+pub enum EvictPolicy<'f, T> {
+    /// The slot taken the longest ago
+    Oldest,
+    /// The slot taken or touched the longest ago
+    LeastRecentlyTouched,
+    /// Visit the slots least recently touched first and evict the first the
+    /// callback accepts. Returning false vetoes e.g. slots with I/O in-flight.
+    Select(&'f mut dyn FnMut(usize, &T) -> bool),
+}
+
+impl<T> core::fmt::Debug for EvictPolicy<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Oldest => write!(f, "Oldest"),
+            Self::LeastRecentlyTouched => write!(f, "LeastRecentlyTouched"),
+            Self::Select(_) => write!(f, "Select"),
+        }
+    }
+}
+
+/// The slot evicted to make room
+#[derive(Debug, PartialEq)]
+pub struct Evicted<T> {
+    /// Key of the evicted slot
+    pub key: usize,
+    /// Item the evicted slot held
+    pub item: T,
+}
+
+/// take_next_or_evict_with() failed handing back the item not taken and the
+/// item evicted if any so that neither is lost
+#[derive(Debug, PartialEq)]
+pub struct EvictError<T> {
+    /// Why the item was not taken
+    pub error: SlabbableError,
+    /// The item given to take, None only if the inner slab failed to take
+    /// the slot it had just reserved
+    pub with: Option<T>,
+    /// The slot evicted before the take failed e.g. as the inner slab did not
+    /// free it right away under a quarantine
+    pub evicted: Option<Evicted<T>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Ticks {
+    taken: u64,
+    touched: u64,
+}
+
+/// Wraps any Slabbable ordering the taken slots by take and by touch so that a
+/// victim can be evicted rather than rejecting at capacity. Slots are touched
+/// upon take, slot_get_mut() and touch().
+#[derive(Debug)]
+pub struct EvictingSlab<S> {
+    inner: S,
+    tick: u64,
+    ticks: HashMap<usize, Ticks>,
+    by_taken: BTreeMap<u64, usize>,
+    by_touched: BTreeMap<u64, usize>,
+}
+
+impl<S> EvictingSlab<S> {
+    /// Order the slots of the given slab
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            tick: 0,
+            ticks: HashMap::new(),
+            by_taken: BTreeMap::new(),
+            by_touched: BTreeMap::new(),
+        }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    #[inline]
+    fn _next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+    fn _on_take(&mut self, slot: usize) {
+        let tick = self._next_tick();
+        let ticks = Ticks {
+            taken: tick,
+            touched: tick,
+        };
+        if let Some(prev) = self.ticks.insert(slot, ticks) {
+            self.by_taken.remove(&prev.taken);
+            self.by_touched.remove(&prev.touched);
+        }
+        self.by_taken.insert(tick, slot);
+        self.by_touched.insert(tick, slot);
+    }
+    fn _on_free(&mut self, slot: usize) {
+        if let Some(prev) = self.ticks.remove(&slot) {
+            self.by_taken.remove(&prev.taken);
+            self.by_touched.remove(&prev.touched);
+        }
+    }
+    /// Mark the slot as recently used. Returns false if the slot isn't taken.
+    pub fn touch(&mut self, slot: usize) -> bool {
+        let tick = self._next_tick();
+        match self.ticks.get_mut(&slot) {
+            Some(ticks) => {
+                self.by_touched.remove(&ticks.touched);
+                ticks.touched = tick;
+                self.by_touched.insert(tick, slot);
+                true
+            }
+            None => false,
+        }
+    }
+    fn _victim<T>(&self, policy: EvictPolicy<'_, T>) -> Result<Option<usize>, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        let select = match policy {
+            EvictPolicy::Oldest => return Ok(self.by_taken.values().next().copied()),
+            EvictPolicy::LeastRecentlyTouched => {
+                return Ok(self.by_touched.values().next().copied())
+            }
+            EvictPolicy::Select(select) => select,
+        };
+        for slot in self.by_touched.values() {
+            let item = match self.inner.slot_get_ref(*slot)? {
+                Some(item) => item,
+                None => return Err(SlabbableError::Bug("Ordered slot is not taken")),
+            };
+            if select(*slot, item) {
+                return Ok(Some(*slot));
+            }
+        }
+        Ok(None)
+    }
+    // Evict the victim the policy selects if at capacity
+    fn _evict<T>(
+        &mut self,
+        policy: EvictPolicy<'_, T>,
+    ) -> Result<Option<Evicted<T>>, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        if self.inner.remaining().is_some() {
+            return Ok(None);
+        }
+        let key = match self._victim(policy)? {
+            Some(key) => key,
+            None => return Err(SlabbableError::AtCapacity(self.inner.capacity())),
+        };
+        let item = self.mark_for_reuse(key)?;
+        Ok(Some(Evicted { key, item }))
+    }
+    /// Take the next free slot or at capacity evict the victim the policy
+    /// selects and take the freed slot. Returns the key taken and the evicted.
+    /// Fails with AtCapacity if no victim was found e.g. all were vetoed or if
+    /// the evicted slot was not freed right away. The items are handed back.
+    pub fn take_next_or_evict_with<T>(
+        &mut self,
+        with: T,
+        policy: EvictPolicy<'_, T>,
+    ) -> Result<(usize, Option<Evicted<T>>), EvictError<T>>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        let evicted = match self._evict(policy) {
+            Ok(evicted) => evicted,
+            Err(error) => {
+                return Err(EvictError {
+                    error,
+                    with: Some(with),
+                    evicted: None,
+                })
+            }
+        };
+        // reserved first so that the item is only given once a slot is free
+        let slot = match self.inner.reserve_next() {
+            Ok(slot) => slot,
+            Err(error) => {
+                return Err(EvictError {
+                    error,
+                    with: Some(with),
+                    evicted,
+                })
+            }
+        };
+        match self.take_reserved_with(slot, with) {
+            Ok(taken) => Ok((taken, evicted)),
+            Err(error) => Err(EvictError {
+                error,
+                with: None,
+                evicted,
+            }),
+        }
+    }
+}
+
+impl<S, T> Slabbable<EvictingSlab<S>, T> for EvictingSlab<S>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self.inner.reserve_next()
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        let taken = self.inner.take_reserved_with(slot, with)?;
+        self._on_take(taken);
+        Ok(taken)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self.inner.cancel_reserved(slot)
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        let taken = self.inner.take_next_with(with)?;
+        self._on_take(taken);
+        Ok(taken)
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        let item = self.inner.mark_for_reuse(slot)?;
+        self._on_free(slot);
+        Ok(item)
+    }
+    /// See trait - touches the slot.
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.touch(slot);
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - the ordering follows the relocated items.
+    fn compact<M, R>(&mut self, movable: M, mut relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        let (ticks, by_taken, by_touched) =
+            (&mut self.ticks, &mut self.by_taken, &mut self.by_touched);
+        self.inner.compact(movable, |relocation, item| {
+            if let Some(moved) = ticks.remove(&relocation.old_key) {
+                ticks.insert(relocation.new_key, moved);
+                by_taken.insert(moved.taken, relocation.new_key);
+                by_touched.insert(moved.touched, relocation.new_key);
+            }
+            relocated(relocation, item)
+        })
+    }
+    /// See trait - the ordering is not accounted.
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn evict_oldest_and_least_recently_touched() {
+        let mut slab = EvictingSlab::new(TestableSlab::<u8>::with_fixed_capacity(3).unwrap());
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        assert!(slab.touch(keys[0]));
+        *slab.slot_get_mut(keys[1]).unwrap().unwrap() += 10;
+
+        let (taken, evicted) = slab
+            .take_next_or_evict_with(3, EvictPolicy::Oldest)
+            .unwrap();
+        assert_eq!(
+            evicted,
+            Some(Evicted {
+                key: keys[0],
+                item: 0
+            })
+        );
+        assert_eq!(slab.slot_get_ref(taken).unwrap(), Some(&3));
+
+        let (_, evicted) = slab
+            .take_next_or_evict_with(4, EvictPolicy::LeastRecentlyTouched)
+            .unwrap();
+        assert_eq!(evicted.unwrap().key, keys[2]);
+        assert!(!slab.touch(keys[2]));
+    }
+
+    #[test]
+    fn select_can_veto() {
+        let mut slab = EvictingSlab::new(TestableSlab::<u8>::with_fixed_capacity(3).unwrap());
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        let mut visited = vec![];
+        let mut in_flight = |key: usize, item: &u8| {
+            visited.push(key);
+            *item == 1
+        };
+        let (_, evicted) = slab
+            .take_next_or_evict_with(3, EvictPolicy::Select(&mut in_flight))
+            .unwrap();
+        assert_eq!(evicted.unwrap().item, 1);
+        assert_eq!(visited, vec![keys[0], keys[1]]);
+
+        let mut veto_all = |_: usize, _: &u8| false;
+        assert_eq!(
+            slab.take_next_or_evict_with(4, EvictPolicy::Select(&mut veto_all)),
+            Err(EvictError {
+                error: SlabbableError::AtCapacity(3),
+                with: Some(4),
+                evicted: None,
+            })
+        );
+    }
+
+    #[test]
+    fn no_eviction_below_capacity() {
+        let mut slab = EvictingSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        slab.reserve_next().unwrap();
+        let (_, evicted) = slab
+            .take_next_or_evict_with(1, EvictPolicy::Oldest)
+            .unwrap();
+        assert_eq!(evicted, None);
+        // the reservation is not a candidate
+        slab.mark_for_reuse(1).unwrap();
+        slab.take_next_with(2).unwrap();
+        assert_eq!(
+            slab.take_next_or_evict_with(3, EvictPolicy::Oldest)
+                .unwrap()
+                .1,
+            Some(Evicted { key: 2, item: 2 })
+        );
+    }
+
+    #[test]
+    fn quarantined_inner_hands_both_back() {
+        let mut inner = TestableSlab::<u8>::with_fixed_capacity(2).unwrap();
+        inner.set_quarantine(Some(Quarantine {
+            frees: 1,
+            ops: usize::MAX,
+        }));
+        let mut slab = EvictingSlab::new(inner);
+        let oldest = slab.take_next_with(0).unwrap();
+        let other = slab.take_next_with(1).unwrap();
+
+        // the evicted slot is held by the quarantine
+        assert_eq!(
+            slab.take_next_or_evict_with(2, EvictPolicy::Oldest),
+            Err(EvictError {
+                error: SlabbableError::AtCapacity(2),
+                with: Some(2),
+                evicted: Some(Evicted {
+                    key: oldest,
+                    item: 0
+                }),
+            })
+        );
+        // another free serves the quarantine of the evicted slot
+        slab.mark_for_reuse(other).unwrap();
+        let taken = slab.take_next_with(2).unwrap();
+        assert_eq!(slab.slot_get_ref(taken).unwrap(), Some(&2));
+    }
+}
@@ -0,0 +1,295 @@
+//! Slabbable byte budget shared across a group of slabs
+
+use core::cell::Cell;
+use std::rc::Rc;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+#[derive(Debug)]
+struct Budget {
+    bytes: Cell<usize>,
+    used: Cell<usize>,
+}
+
+/// Byte budget shared by the slabs registered with it. Clones share the budget.
+#[derive(Clone, Debug)]
+pub struct SlabGroup {
+    budget: Rc<Budget>,
+}
+
+impl SlabGroup {
+    /// Group with the given total bytes
+    pub fn new(budget_bytes: usize) -> Self {
+        Self {
+            budget: Rc::new(Budget {
+                bytes: Cell::new(budget_bytes),
+                used: Cell::new(0),
+            }),
+        }
+    }
+    /// Register the slab charging each reservation or take of T with
+    /// size_of::<T>() plus the extra bytes declared e.g. for heap the item owns.
+    pub fn register<S>(&self, inner: S, extra_bytes: usize) -> BudgetedSlab<S> {
+        BudgetedSlab {
+            inner,
+            group: self.clone(),
+            extra_bytes,
+            charged: 0,
+        }
+    }
+    /// Total bytes of the budget
+    pub fn budget(&self) -> usize {
+        self.budget.bytes.get()
+    }
+    /// Change the total bytes. Lowering it below used only rejects until the
+    /// group falls under it.
+    pub fn set_budget(&self, budget_bytes: usize) {
+        self.budget.bytes.set(budget_bytes);
+    }
+    /// Bytes charged across the group
+    pub fn used(&self) -> usize {
+        self.budget.used.get()
+    }
+    /// Bytes left in the budget
+    pub fn available(&self) -> usize {
+        self.budget().saturating_sub(self.used())
+    }
+    fn _charge(&self, bytes: usize) -> bool {
+        if bytes > self.available() {
+            return false;
+        }
+        self.budget.used.set(self.used() + bytes);
+        true
+    }
+    fn _refund(&self, bytes: usize) {
+        self.budget.used.set(self.used().saturating_sub(bytes));
+    }
+}
+
+/// Slab registered with a SlabGroup through SlabGroup::register(). Fails with
+/// AtCapacity once the group budget is exhausted even if the slab has room.
+#[derive(Debug)]
+pub struct BudgetedSlab<S> {
+    inner: S,
+    group: SlabGroup,
+    extra_bytes: usize,
+    // bytes this slab has charged against the group
+    charged: usize,
+}
+
+impl<S> BudgetedSlab<S> {
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// The group charged
+    pub fn group(&self) -> &SlabGroup {
+        &self.group
+    }
+    /// Bytes this slab has charged against the group
+    pub fn charged(&self) -> usize {
+        self.charged
+    }
+    #[inline]
+    fn _slot_bytes<T>(&self) -> usize {
+        size_of::<T>() + self.extra_bytes
+    }
+    fn _charge<T>(&mut self) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, T>,
+    {
+        let bytes = self._slot_bytes::<T>();
+        if !self.group._charge(bytes) {
+            return Err(SlabbableError::AtCapacity(self.inner.capacity()));
+        }
+        self.charged += bytes;
+        Ok(())
+    }
+    fn _refund<T>(&mut self) {
+        let bytes = self._slot_bytes::<T>();
+        self.group._refund(bytes);
+        self.charged -= bytes;
+    }
+}
+
+impl<S> Drop for BudgetedSlab<S> {
+    fn drop(&mut self) {
+        self.group._refund(self.charged);
+    }
+}
+
+impl<S, T> Slabbable<BudgetedSlab<S>, T> for BudgetedSlab<S>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait - registered with a group of its own without budget limit.
+    /// Use SlabGroup::register() to share a budget.
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(SlabGroup::new(usize::MAX).register(S::with_fixed_capacity(cap)?, 0))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self._charge::<T>()?;
+        self.inner
+            .reserve_next()
+            .inspect_err(|_| self._refund::<T>())
+    }
+    /// See trait - charged upon reserve already.
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_reserved_with(slot, with)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self.inner.cancel_reserved(slot)?;
+        self._refund::<T>();
+        Ok(())
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self._charge::<T>()?;
+        self.inner
+            .take_next_with(with)
+            .inspect_err(|_| self._refund::<T>())
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        let item = self.inner.mark_for_reuse(slot)?;
+        self._refund::<T>();
+        Ok(item)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait - the slots left of the slab or the group budget whichever is less.
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        let by_budget = self.group.available() / self._slot_bytes::<T>().max(1);
+        match self.inner.remaining()?.min(by_budget) {
+            0 => None,
+            rem => Some(rem),
+        }
+    }
+    /// See trait
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait
+    fn compact<M, R>(&mut self, movable: M, relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        self.inner.compact(movable, relocated)
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn shared_budget() {
+        let group = SlabGroup::new(64);
+        let mut conns = group.register(TestableSlab::<u64>::with_fixed_capacity(10).unwrap(), 8);
+        let mut ops = group.register(TestableSlab::<u32>::with_fixed_capacity(10).unwrap(), 0);
+
+        // 16 bytes per connection
+        let conn = conns.take_next_with(1).unwrap();
+        let reserved = conns.reserve_next().unwrap();
+        assert_eq!(group.used(), 32);
+        assert_eq!(conns.remaining(), Some(2));
+        // 4 bytes per op
+        for i in 0..8 {
+            ops.take_next_with(i).unwrap();
+        }
+        assert_eq!(group.available(), 0);
+        assert_eq!(ops.take_next_with(8), Err(SlabbableError::AtCapacity(10)));
+        assert_eq!(conns.remaining(), None);
+
+        conns.take_reserved_with(reserved, 2).unwrap();
+        conns.mark_for_reuse(conn).unwrap();
+        assert_eq!(group.available(), 16);
+        // the budget would allow 4 more ops but the slab only has 2
+        assert_eq!(ops.remaining(), Some(2));
+        assert_eq!((conns.charged(), ops.charged()), (16, 32));
+
+        drop(ops);
+        assert_eq!(group.used(), 16);
+    }
+}
@@ -39,6 +39,9 @@
 //! C. Not re-using sequential usize id until it is recycled at usize::MAX
 //! D; Ability to free-up memory e.g. in acses of ramp-up / down high/low loads.
 //! E. Minimal memory usage for free slots
+
+extern crate alloc;
+
 /// See module documentation of guarantees needed.
 pub trait Slabbable<Slabber, T> {
     /// Error
@@ -50,6 +53,14 @@ pub trait Slabbable<Slabber, T> {
     fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error>;
     /// Take the previously reserved slot
     fn take_reserved_with(&mut self, _: ReservedSlot, _: T) -> Result<usize, Self::Error>;
+    /// Release the previously reserved slot without taking it.
+    /// Implementations that can't release reservations are refused with Unsupported.
+    fn cancel_reserved(&mut self, _: ReservedSlot) -> Result<(), Self::Error>
+    where
+        Self::Error: From<SlabbableError>,
+    {
+        Err(SlabbableError::Unsupported.into())
+    }
     /// Take the next free slot, ideally with least re-used ID and return it's key ID
     fn take_next_with(&mut self, _: T) -> Result<usize, Self::Error>;
     /// Mark a given slot for re-use
@@ -62,6 +73,26 @@ pub trait Slabbable<Slabber, T> {
     fn capacity(&self) -> usize;
     /// Remaining capacity of teh slab-slotmap
     fn remaining(&self) -> Option<usize>;
+    /// Change the fixed capacity keeping the sequential ID state and the live keys.
+    /// Must be refused with NotEmpty while slots are taken or reserved unless the
+    /// caller asserts through ResizeMode that no addresses of the items are held,
+    /// and with BelowOccupied if the live keys would not fit the new capacity.
+    /// Implementations that can't resize are refused with Unsupported.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        _new_cap: usize,
+        _mode: ResizeMode,
+    ) -> Result<(), Self::Error>
+    where
+        Self::Error: From<SlabbableError>,
+    {
+        Err(SlabbableError::Unsupported.into())
+    }
+    /// Memory held by the slab-slotmap in bytes for capacity planning.
+    /// Implementations that don't account it report zero.
+    fn memory_usage(&self) -> SlabMemory {
+        SlabMemory::default()
+    }
     /// Reap memory that can be freed opportunistically-optionally but keep the capacity intanct.
     /// This may mean wiping out entries at the tail and re-allocating at the end etc.
     /// Implementations that don't provide this should return None and the ones providing it
@@ -69,6 +100,93 @@ pub trait Slabbable<Slabber, T> {
     /// This is an opportunity to reap the freelist or gc in the periods that may afford slowness
     /// traded for opportunity to free up operating memory.
     fn reap(&mut self) -> Option<usize>;
+    /// Reap within the given budget resuming from where the previous call left off.
+    /// This allows spreading the reclamation across idle periods without stalling.
+    /// Implementations that can't bound the work fall back into reap() in one go.
+    fn reap_bounded(&mut self, _budget: ReapBudget) -> ReapProgress {
+        match self.reap() {
+            Some(reaped) => ReapProgress::Done(reaped),
+            None => ReapProgress::Unsupported,
+        }
+    }
+    /// Set or clear the policy deciding when reaping is worthwhile, evaluated on
+    /// mark_for_reuse. Implementations that don't reap ignore it.
+    fn set_reap_policy(&mut self, _policy: Option<ReapPolicy>) {}
+    /// Whether the reap policy deems reaping worthwhile.
+    fn reap_due(&self) -> bool {
+        false
+    }
+    /// Set or clear the quarantine delaying the reuse of freed slots. Clearing it
+    /// releases the slots held. Implementations that don't hand freed keys out
+    /// again soon e.g. with sequential IDs ignore it.
+    fn set_quarantine(&mut self, _quarantine: Option<Quarantine>) {}
+    /// Where new items are placed among the free slots if the implementation
+    /// lets it be chosen, None if e.g. hashed.
+    fn placement(&self) -> Option<Placement> {
+        None
+    }
+    /// Choose where new items are placed. Implementations that can't honour the
+    /// given placement must return false and keep placing as before.
+    fn set_placement(&mut self, _placement: Placement) -> bool {
+        false
+    }
+    /// Keep the issued keys below limit, the sequential IDs wrapping around there
+    /// instead of at usize::MAX. Implementations that can't must return false.
+    fn set_key_limit(&mut self, _limit: usize) -> bool {
+        false
+    }
+    /// Move the taken items the caller deems movable e.g. not referenced by the kernel
+    /// into a dense prefix so that reap() can release the tail, trading the address
+    /// stability for memory at times the caller chooses. Each move is notified with
+    /// the item at its new address. Reserved slots stay put.
+    /// Implementations that don't compact should return None, otherwise the moves.
+    fn compact<M, R>(&mut self, _movable: M, _relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        None
+    }
+    /// Runtime statistics if the implementation maintains them.
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        None
+    }
+    /// Zero the statistics counters and restart the high-watermark.
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {}
+}
+
+/// Conditions try_resize_fixed_capacity() may move the items under.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResizeMode {
+    /// Only resize an empty slab
+    IfEmpty,
+    /// The caller asserts no addresses of the items are held e.g. by the kernel
+    /// so the items may be moved
+    NoAddressesHeld,
+}
+
+/// Where new items are placed among the free slots.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placement {
+    /// Lowest free slot first so that live items cluster at the front after a
+    /// ramp-down and the tail can be reaped
+    LowestFree,
+    /// Slot freed the longest ago first
+    Fifo,
+    /// Slot freed the most recently first
+    Lifo,
+}
+
+/// Item moved by compact(). The keys are the same if the implementation keeps
+/// the keys stable and only the address changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Relocation {
+    /// Key the item was at
+    pub old_key: usize,
+    /// Key the item is at now
+    pub new_key: usize,
 }
 
 /// Reserved marked for any slot that can be taken later.
@@ -102,13 +220,88 @@ mod error;
 #[doc(inline)]
 pub use error::SlabbableError;
 
+mod memory;
+#[doc(inline)]
+pub use memory::SlabMemory;
+
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "stats")]
+#[doc(inline)]
+pub use stats::SlabStats;
+
+mod reap;
+#[doc(inline)]
+pub use reap::{ReapAction, ReapBudget, ReapPolicy, ReapProgress, ReapTracker};
+
+mod quarantine;
+#[doc(inline)]
+pub use quarantine::{Quarantine, QuarantineQueue};
+
+/// Slot age tracking
+#[cfg(feature = "age")]
+pub mod age;
+
+/// Per-slot deadlines
+#[cfg(feature = "deadline")]
+pub mod deadline;
+
+/// Occupancy watermarks for backpressure
+#[cfg(feature = "watermark")]
+pub mod watermark;
+
+/// Async waiting for capacity
+#[cfg(feature = "async")]
+pub mod async_slab;
+
+/// Eviction at capacity
+#[cfg(feature = "evict")]
+pub mod evict;
+
+/// Headroom for priority classes
+#[cfg(feature = "priority")]
+pub mod priority;
+
+/// Per-tenant partitions with quotas
+#[cfg(feature = "partition")]
+pub mod partition;
+
+/// Byte budget shared across slabs
+#[cfg(feature = "group")]
+pub mod group;
+
+/// Overflow spill-over beyond the fixed capacity
+#[cfg(feature = "overflow")]
+pub mod overflow;
+
+/// Two-phase retire / reclaim of slots the kernel may still reference
+#[cfg(feature = "retire")]
+pub mod retire;
+
+/// Slot ownership states between the user and the kernel
+#[cfg(feature = "ownership")]
+pub mod ownership;
+
+/// Reference-counted slots held by several outstanding operations
+#[cfg(feature = "pin")]
+pub mod pin;
+
+/// Parent / child linked slots freed as a tree
+#[cfg(feature = "link")]
+pub mod link;
+
+/// Reaping driven by Linux memory pressure
+#[cfg(all(feature = "pressure", target_os = "linux"))]
+pub mod pressure;
+
 #[cfg(test)]
 mod testable;
 
 #[cfg(test)]
+#[allow(clippy::unwrap_used)]
 mod test {
     use super::testable::*;
-    use super::Slabbable;
+    use super::{ReapBudget, ReapProgress, Slabbable};
     use rstest::rstest;
 
     #[repr(packed, C)]
@@ -163,4 +356,26 @@ mod test {
             assert_eq!(ptr, chk_ptr);
         }
     }
+
+    #[test]
+    fn testable_ids_wrap_around() {
+        let mut impl_ut = TestableSlab::<u8>::with_fixed_capacity(2).unwrap();
+        impl_ut.skip_ids_to(usize::MAX);
+        assert_eq!(impl_ut.take_next_with(0).unwrap(), usize::MAX);
+        assert_eq!(impl_ut.take_next_with(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn reap_bounded_falls_back_to_reap() {
+        let mut impl_ut = match TestableSlab::<SomeCStruct>::with_fixed_capacity(10) {
+            Ok(impl_ut) => impl_ut,
+            _ => panic!("Could not create slab"),
+        };
+        assert_eq!(
+            impl_ut.reap_bounded(ReapBudget::Slots(1)),
+            ReapProgress::Unsupported
+        );
+        assert_eq!(ReapBudget::Bytes(100).as_slots(8), 12);
+        assert_eq!(ReapBudget::Bytes(1).as_slots(8), 1);
+    }
 }
@@ -0,0 +1,414 @@
+//! Slabbable parent / child linked slots
+
+use core::marker::PhantomData;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Item stored in the wrapped slab along with its links so that the tree
+/// lives in the slots themselves without allocating on its own
+#[derive(Clone, Debug)]
+pub struct Linked<T> {
+    item: T,
+    parent: Option<usize>,
+    first_child: Option<usize>,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl<T> Linked<T> {
+    #[inline]
+    fn new(item: T, parent: Option<usize>, next: Option<usize>) -> Self {
+        Self {
+            item,
+            parent,
+            first_child: None,
+            prev: None,
+            next,
+        }
+    }
+    #[inline]
+    fn is_linked(&self) -> bool {
+        self.parent.is_some() || self.first_child.is_some()
+    }
+}
+
+/// Keys of the children of a slot, the latest taken first
+#[derive(Debug)]
+pub struct Children<'a, S, T> {
+    inner: &'a S,
+    next: Option<usize>,
+    _item: PhantomData<T>,
+}
+
+impl<S, T> Iterator for Children<'_, S, T>
+where
+    S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+{
+    type Item = usize;
+    fn next(&mut self) -> Option<usize> {
+        let key = self.next?;
+        self.next = self
+            .inner
+            .slot_get_ref(key)
+            .ok()
+            .flatten()
+            .and_then(|node| node.next);
+        Some(key)
+    }
+}
+
+/// Wraps any Slabbable of Linked items letting a slot own child slots e.g. a
+/// connection owning its in-flight operations. The whole tree can be freed at
+/// once through mark_tree_for_reuse(). Linked slots are never compacted.
+#[derive(Debug)]
+pub struct LinkedSlab<S> {
+    inner: S,
+}
+
+impl<S> LinkedSlab<S> {
+    /// Link the slots of the given slab
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    #[inline]
+    fn _node<T>(&self, slot: usize) -> Result<&Linked<T>, SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        self.inner
+            .slot_get_ref(slot)?
+            .ok_or(SlabbableError::InvalidIndex(slot))
+    }
+    #[inline]
+    fn _node_mut<T>(&mut self, slot: usize) -> Result<&mut Linked<T>, SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        self.inner
+            .slot_get_mut(slot)?
+            .ok_or(SlabbableError::InvalidIndex(slot))
+    }
+    // Detach the slot from its parent and siblings
+    fn _unlink<T>(&mut self, slot: usize) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        let node = self._node_mut::<T>(slot)?;
+        let (parent, prev, next) = (node.parent.take(), node.prev.take(), node.next.take());
+        match (prev, parent) {
+            (Some(prev), _) => self._node_mut::<T>(prev)?.next = next,
+            (None, Some(parent)) => self._node_mut::<T>(parent)?.first_child = next,
+            (None, None) => {}
+        }
+        if let Some(next) = next {
+            self._node_mut::<T>(next)?.prev = prev;
+        }
+        Ok(())
+    }
+    /// Take the next slot as a child of the given taken parent slot
+    pub fn take_child_with<T>(&mut self, parent: usize, with: T) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        let first = self._node::<T>(parent)?.first_child;
+        let child = self
+            .inner
+            .take_next_with(Linked::new(with, Some(parent), first))?;
+        if let Some(first) = first {
+            self._node_mut::<T>(first)?.prev = Some(child);
+        }
+        self._node_mut::<T>(parent)?.first_child = Some(child);
+        Ok(child)
+    }
+    /// Keys of the children of the given slot
+    pub fn children<T>(&self, parent: usize) -> Result<Children<'_, S, T>, SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        Ok(Children {
+            inner: &self.inner,
+            next: self._node::<T>(parent)?.first_child,
+            _item: PhantomData,
+        })
+    }
+    /// Parent of the given slot if it is a child
+    pub fn parent<T>(&self, slot: usize) -> Result<Option<usize>, SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        Ok(self._node::<T>(slot)?.parent)
+    }
+    // Every slot of the tree under the given one is taken
+    fn _check_tree<T>(&self, root: usize) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        let mut parents = vec![root];
+        while let Some(parent) = parents.pop() {
+            let mut child = self._node::<T>(parent)?.first_child;
+            while let Some(key) = child {
+                parents.push(key);
+                child = self._node::<T>(key)?.next;
+            }
+        }
+        Ok(())
+    }
+    /// Free the given slot along with all its descendants returning the items
+    /// bottom-up, every child before its parent and the given slot last. The
+    /// whole tree is checked before anything is freed.
+    pub fn mark_tree_for_reuse<T>(&mut self, root: usize) -> Result<Vec<T>, SlabbableError>
+    where
+        S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+    {
+        self._check_tree::<T>(root)?;
+        let mut items = Vec::new();
+        let mut cur = root;
+        loop {
+            while let Some(child) = self._node::<T>(cur)?.first_child {
+                cur = child;
+            }
+            // the tree stays attached until the root goes
+            if cur == root {
+                self._unlink::<T>(root)?;
+            }
+            let node = self.inner.mark_for_reuse(cur)?;
+            items.push(node.item);
+            if cur == root {
+                return Ok(items);
+            }
+            // always the first child of its parent by now
+            let parent = node
+                .parent
+                .ok_or(SlabbableError::Bug("Child without parent"))?;
+            self._node_mut::<T>(parent)?.first_child = node.next;
+            if let Some(next) = node.next {
+                self._node_mut::<T>(next)?.prev = None;
+            }
+            cur = parent;
+        }
+    }
+}
+
+impl<S, T> Slabbable<LinkedSlab<S>, T> for LinkedSlab<S>
+where
+    S: Slabbable<S, Linked<T>, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self.inner.reserve_next()
+    }
+    /// See trait - taken without a parent.
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        self.inner
+            .take_reserved_with(slot, Linked::new(with, None, None))
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self.inner.cancel_reserved(slot)
+    }
+    /// See trait - taken without a parent, see take_child_with().
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_next_with(Linked::new(with, None, None))
+    }
+    /// See trait - the slot is unlinked and its children left without a
+    /// parent, see mark_tree_for_reuse().
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        let mut child = self._node::<T>(slot)?.first_child;
+        while let Some(key) = child {
+            let node = self._node_mut::<T>(key)?;
+            child = node.next.take();
+            node.parent = None;
+            node.prev = None;
+        }
+        self._unlink::<T>(slot)?;
+        self.inner.mark_for_reuse(slot).map(|node| node.item)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        Ok(self.inner.slot_get_mut(slot)?.map(|node| &mut node.item))
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        Ok(self.inner.slot_get_ref(slot)?.map(|node| &node.item))
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - linked slots never move as the links would dangle.
+    fn compact<M, R>(&mut self, mut movable: M, mut relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        self.inner.compact(
+            |slot, node| !node.is_linked() && movable(slot, &node.item),
+            |relocation, node| relocated(relocation, &node.item),
+        )
+    }
+    /// See trait - the links included.
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    type Slab = LinkedSlab<TestableSlab<Linked<u8>>>;
+
+    #[test]
+    fn tree_freed_bottom_up() {
+        let mut slab = Slab::new(TestableSlab::with_fixed_capacity(8).unwrap());
+        let conn = slab.take_next_with(0).unwrap();
+        let a = slab.take_child_with(conn, 1).unwrap();
+        let b = slab.take_child_with(conn, 2).unwrap();
+        let a1 = slab.take_child_with(a, 3).unwrap();
+        let other = slab.take_next_with(9).unwrap();
+
+        assert_eq!(
+            slab.children::<u8>(conn).unwrap().collect::<Vec<_>>(),
+            vec![b, a]
+        );
+        assert_eq!(slab.parent::<u8>(a1), Ok(Some(a)));
+        assert_eq!(slab.parent::<u8>(conn), Ok(None));
+
+        assert_eq!(slab.mark_tree_for_reuse(conn).unwrap(), vec![2, 3, 1, 0]);
+        assert_eq!(slab.remaining(), Some(7));
+        assert_eq!(slab.slot_get_ref(other).unwrap(), Some(&9));
+        assert_eq!(
+            slab.mark_tree_for_reuse::<u8>(conn),
+            Err(SlabbableError::InvalidIndex(conn))
+        );
+    }
+
+    #[test]
+    fn subtree_and_single_slots_unlink() {
+        let mut slab = Slab::new(TestableSlab::with_fixed_capacity(8).unwrap());
+        let conn = slab.take_next_with(0).unwrap();
+        let a = slab.take_child_with(conn, 1).unwrap();
+        let b = slab.take_child_with(conn, 2).unwrap();
+        let c = slab.take_child_with(conn, 3).unwrap();
+        let b1 = slab.take_child_with(b, 4).unwrap();
+
+        // the middle sibling with its own child
+        assert_eq!(slab.mark_tree_for_reuse(b).unwrap(), vec![4, 2]);
+        assert_eq!(
+            slab.children::<u8>(conn).unwrap().collect::<Vec<_>>(),
+            vec![c, a]
+        );
+        assert_eq!(slab.parent::<u8>(b1), Err(SlabbableError::InvalidIndex(b1)));
+
+        // freeing the parent alone orphans the children
+        assert_eq!(slab.mark_for_reuse(conn), Ok(0));
+        assert_eq!(slab.parent::<u8>(a), Ok(None));
+        assert_eq!(slab.children::<u8>(a).unwrap().count(), 0);
+        assert_eq!(slab.mark_for_reuse(c), Ok(3));
+        assert_eq!(slab.mark_tree_for_reuse(a).unwrap(), vec![1]);
+        assert_eq!(slab.remaining(), Some(8));
+    }
+
+    #[test]
+    fn broken_tree_is_left_whole() {
+        let mut slab = Slab::new(TestableSlab::with_fixed_capacity(8).unwrap());
+        let conn = slab.take_next_with(0).unwrap();
+        let a = slab.take_child_with(conn, 1).unwrap();
+        let a1 = slab.take_child_with(a, 2).unwrap();
+        let b = slab.take_child_with(a, 3).unwrap();
+        let b1 = slab.take_child_with(b, 4).unwrap();
+        // freed behind our back leaving a dangling link
+        slab.inner.mark_for_reuse(b1).unwrap();
+
+        assert_eq!(
+            slab.mark_tree_for_reuse::<u8>(a),
+            Err(SlabbableError::InvalidIndex(b1))
+        );
+        assert_eq!(slab.parent::<u8>(a), Ok(Some(conn)));
+        assert_eq!(
+            slab.children::<u8>(a).unwrap().collect::<Vec<_>>(),
+            vec![b, a1]
+        );
+        assert_eq!(slab.slot_get_ref(a1).unwrap(), Some(&2));
+        assert_eq!(slab.remaining(), Some(4));
+    }
+}
@@ -0,0 +1,37 @@
+//! Slabbable memory accounting
+
+/// Memory held by the slab-slotmap in bytes
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlabMemory {
+    /// Bytes currently allocated for the slots, free ones included
+    pub allocated_bytes: usize,
+    /// Bytes of the allocation used by taken or reserved slots
+    pub live_bytes: usize,
+    /// Bytes of the allocation reap() would release right now
+    pub reclaimable_bytes: usize,
+    /// Bytes each slot takes on top of the item itself e.g. keys, tags or
+    /// control bytes, amortized over the slots allocated
+    pub overhead_per_slot: usize,
+}
+
+impl SlabMemory {
+    /// Account slots_allocated slots of which slots_live are in use given the
+    /// total allocation and the size of the item.
+    pub fn from_slots(
+        allocated_bytes: usize,
+        slots_allocated: usize,
+        slots_live: usize,
+        item_size: usize,
+    ) -> Self {
+        let overhead_per_slot = match slots_allocated {
+            0 => 0,
+            _ => (allocated_bytes / slots_allocated).saturating_sub(item_size),
+        };
+        Self {
+            allocated_bytes,
+            live_bytes: slots_live * (item_size + overhead_per_slot),
+            reclaimable_bytes: 0,
+            overhead_per_slot,
+        }
+    }
+}
@@ -0,0 +1,393 @@
+//! Slabbable overflow spill-over
+
+use std::collections::BTreeSet;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Keys of the overflow area have this bit set. The primary is asked to keep
+/// its keys below it through set_key_limit() so its sequential IDs wrap around
+/// there. Should it still issue a key with the bit set the key is handed back
+/// and the item spilled over instead so that the key spaces never collide.
+pub const OVERFLOW_KEY_BIT: usize = 1 << (usize::BITS - 1);
+
+/// Whether the key refers to the overflow area
+#[inline]
+pub fn is_overflow_key(key: usize) -> bool {
+    key & OVERFLOW_KEY_BIT != 0
+}
+
+/// Overflow usage for sizing the primary
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OverflowStats {
+    /// Reservations and takes that spilled into the overflow area
+    pub spills: u64,
+    /// Overflow slots currently reserved or taken
+    pub live: usize,
+    /// Highest live since creation
+    pub high_watermark: usize,
+    /// Overflow slots currently allocated
+    pub allocated: usize,
+}
+
+#[derive(Debug)]
+enum OverflowEntry<T> {
+    Reserved,
+    // boxed so the address stays as the area grows and shrinks
+    Taken(Box<T>),
+}
+
+/// Wraps any Slabbable spilling reservations and takes over into a heap backed
+/// and address-stable overflow area once the primary is AtCapacity. New items
+/// prefer the primary so the overflow drains and shrinks as the load falls.
+#[derive(Debug)]
+pub struct OverflowSlab<S, T> {
+    inner: S,
+    // None = free
+    area: Vec<Option<OverflowEntry<T>>>,
+    free: BTreeSet<usize>,
+    limit: Option<usize>,
+    stats: OverflowStats,
+}
+
+impl<S, T> OverflowSlab<S, T> {
+    /// Spill over from the given slab into at most limit overflow slots, None
+    /// for no limit upon which AtCapacity is returned again
+    pub fn new(mut inner: S, limit: Option<usize>) -> Self
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        inner.set_key_limit(OVERFLOW_KEY_BIT);
+        Self {
+            inner,
+            area: Vec::new(),
+            free: BTreeSet::new(),
+            limit,
+            stats: OverflowStats::default(),
+        }
+    }
+    /// The wrapped primary slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// Overflow usage
+    pub fn overflow_stats(&self) -> OverflowStats {
+        OverflowStats {
+            allocated: self.area.len(),
+            ..self.stats
+        }
+    }
+    #[inline]
+    fn _index(key: usize) -> usize {
+        key & !OVERFLOW_KEY_BIT
+    }
+    fn _spill(&mut self, entry: OverflowEntry<T>) -> Result<usize, SlabbableError> {
+        if let Some(limit) = self.limit {
+            if self.stats.live >= limit {
+                return Err(SlabbableError::AtCapacity(limit));
+            }
+        }
+        let idx = match self.free.pop_first() {
+            Some(idx) => {
+                self.area[idx] = Some(entry);
+                idx
+            }
+            None => {
+                self.area.push(Some(entry));
+                self.area.len() - 1
+            }
+        };
+        self.stats.spills += 1;
+        self.stats.live += 1;
+        self.stats.high_watermark = self.stats.high_watermark.max(self.stats.live);
+        Ok(idx | OVERFLOW_KEY_BIT)
+    }
+    fn _entry_mut(&mut self, key: usize) -> Option<&mut Option<OverflowEntry<T>>> {
+        self.area.get_mut(Self::_index(key))
+    }
+    // Free the overflow slot shrinking the area from the tail
+    fn _release(&mut self, key: usize) -> Result<OverflowEntry<T>, SlabbableError> {
+        let idx = Self::_index(key);
+        let entry = match self.area.get_mut(idx).and_then(Option::take) {
+            Some(entry) => entry,
+            None => return Err(SlabbableError::InvalidIndex(key)),
+        };
+        self.stats.live -= 1;
+        self.free.insert(idx);
+        while let Some(None) = self.area.last() {
+            self.area.pop();
+            self.free.pop_last();
+        }
+        if self.area.len() <= self.area.capacity() / 4 {
+            self.area.shrink_to_fit();
+        }
+        Ok(entry)
+    }
+}
+
+impl<S, T> Slabbable<OverflowSlab<S, T>, T> for OverflowSlab<S, T>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait - with no overflow limit.
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?, None))
+    }
+    /// See trait - spills over when the primary is at capacity.
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        if self.inner.remaining().is_some() {
+            let slot = self.inner.reserve_next()?;
+            if !is_overflow_key(slot.id()) {
+                return Ok(slot);
+            }
+            self.inner.cancel_reserved(slot)?;
+        }
+        Ok(ReservedSlot::issue(self._spill(OverflowEntry::Reserved)?))
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        let key = slot.id();
+        if !is_overflow_key(key) {
+            return self.inner.take_reserved_with(slot, with);
+        }
+        match self._entry_mut(key) {
+            Some(entry @ Some(OverflowEntry::Reserved)) => {
+                *entry = Some(OverflowEntry::Taken(Box::new(with)));
+                Ok(key)
+            }
+            _ => Err(SlabbableError::InvalidIndex(key)),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        let key = slot.id();
+        if !is_overflow_key(key) {
+            return self.inner.cancel_reserved(slot);
+        }
+        match self._entry_mut(key) {
+            Some(Some(OverflowEntry::Reserved)) => self._release(key).map(|_| ()),
+            _ => Err(SlabbableError::InvalidIndex(key)),
+        }
+    }
+    /// See trait - spills over when the primary is at capacity.
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        if self.inner.remaining().is_none() {
+            return self._spill(OverflowEntry::Taken(Box::new(with)));
+        }
+        let key = self.inner.take_next_with(with)?;
+        if !is_overflow_key(key) {
+            return Ok(key);
+        }
+        let with = self.inner.mark_for_reuse(key)?;
+        self._spill(OverflowEntry::Taken(Box::new(with)))
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        if !is_overflow_key(slot) {
+            return self.inner.mark_for_reuse(slot);
+        }
+        match self._entry_mut(slot) {
+            Some(Some(OverflowEntry::Taken(_))) => {}
+            _ => return Err(SlabbableError::InvalidIndex(slot)),
+        }
+        match self._release(slot)? {
+            OverflowEntry::Taken(item) => Ok(*item),
+            OverflowEntry::Reserved => Err(SlabbableError::Bug("Taken slot vanished upon remove")),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        if !is_overflow_key(slot) {
+            return self.inner.slot_get_mut(slot);
+        }
+        match self._entry_mut(slot) {
+            Some(Some(OverflowEntry::Taken(item))) => Ok(Some(item)),
+            _ => Ok(None),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        if !is_overflow_key(slot) {
+            return self.inner.slot_get_ref(slot);
+        }
+        match self.area.get(Self::_index(slot)) {
+            Some(Some(OverflowEntry::Taken(item))) => Ok(Some(item)),
+            _ => Ok(None),
+        }
+    }
+    /// See trait - of the primary.
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait - of the primary.
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait - of the primary, the overflow area is kept.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait - of the primary.
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait - of the primary.
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait - of the primary.
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait - the overflow keys take the upper half of the key space.
+    fn set_key_limit(&mut self, _limit: usize) -> bool {
+        false
+    }
+    /// See trait - of the primary, the overflow area is not compacted.
+    fn compact<M, R>(&mut self, movable: M, relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        self.inner.compact(movable, relocated)
+    }
+    /// See trait - the overflow area included.
+    fn memory_usage(&self) -> SlabMemory {
+        let primary = self.inner.memory_usage();
+        let boxed = self.stats.live * size_of::<T>();
+        let area = self.area.capacity() * size_of::<Option<OverflowEntry<T>>>();
+        SlabMemory {
+            allocated_bytes: primary.allocated_bytes + area + boxed,
+            live_bytes: primary.live_bytes + boxed,
+            ..primary
+        }
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait - of the primary, see overflow_stats().
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn spill_and_drain() {
+        let mut slab =
+            OverflowSlab::new(TestableSlab::<u8>::with_fixed_capacity(1).unwrap(), Some(2));
+        let primary = slab.take_next_with(0).unwrap();
+        assert!(!is_overflow_key(primary));
+
+        let spilled = slab.take_next_with(1).unwrap();
+        assert!(is_overflow_key(spilled));
+        let ptr = core::ptr::addr_of!(*slab.slot_get_ref(spilled).unwrap().unwrap());
+        let reserved = slab.reserve_next().unwrap();
+        assert!(is_overflow_key(reserved.id()));
+        assert_eq!(slab.take_next_with(3), Err(SlabbableError::AtCapacity(2)));
+
+        let late = slab.take_reserved_with(reserved, 2).unwrap();
+        *slab.slot_get_mut(late).unwrap().unwrap() += 1;
+        assert_eq!(slab.slot_get_ref(late).unwrap(), Some(&3));
+        assert_eq!(
+            slab.overflow_stats(),
+            OverflowStats {
+                spills: 2,
+                live: 2,
+                high_watermark: 2,
+                allocated: 2,
+            }
+        );
+
+        // drains from the tail keeping the addresses of the rest
+        assert_eq!(slab.mark_for_reuse(late).unwrap(), 3);
+        assert_eq!(slab.overflow_stats().allocated, 1);
+        assert_eq!(
+            core::ptr::addr_of!(*slab.slot_get_ref(spilled).unwrap().unwrap()),
+            ptr
+        );
+        assert_eq!(slab.mark_for_reuse(spilled).unwrap(), 1);
+        assert_eq!(slab.overflow_stats().allocated, 0);
+        assert_eq!(
+            slab.mark_for_reuse(spilled),
+            Err(SlabbableError::InvalidIndex(spilled))
+        );
+
+        // the primary is preferred once it has room
+        slab.mark_for_reuse(primary).unwrap();
+        assert!(!is_overflow_key(slab.take_next_with(4).unwrap()));
+        assert_eq!(slab.overflow_stats().spills, 2);
+    }
+
+    #[test]
+    fn primary_ids_wrap_below_overflow_bit() {
+        let mut primary = TestableSlab::<u8>::with_fixed_capacity(2).unwrap();
+        primary.skip_ids_to(OVERFLOW_KEY_BIT - 1);
+        let mut slab = OverflowSlab::new(primary, Some(1));
+        assert!(!slab.set_key_limit(usize::MAX));
+
+        let last = slab.take_next_with(1).unwrap();
+        assert_eq!(last, OVERFLOW_KEY_BIT - 1);
+        // the primary wraps around instead of reaching the overflow key space
+        let wrapped = slab.take_next_with(2).unwrap();
+        assert_eq!(wrapped, 0);
+        assert_eq!(slab.overflow_stats().live, 0);
+        assert_eq!(slab.inner().remaining(), None);
+
+        // only spills over once the primary is full
+        let spilled = slab.reserve_next().unwrap();
+        assert!(is_overflow_key(spilled.id()));
+        assert_eq!(slab.overflow_stats().live, 1);
+        slab.cancel_reserved(spilled).unwrap();
+
+        assert_eq!(slab.mark_for_reuse(last).unwrap(), 1);
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(reserved.id(), 1);
+        assert_eq!(slab.overflow_stats().live, 0);
+        assert_eq!(slab.take_reserved_with(reserved, 3).unwrap(), 1);
+        assert_eq!(slab.slot_get_ref(1).unwrap(), Some(&3));
+    }
+}
@@ -0,0 +1,443 @@
+//! Slabbable slot ownership states
+
+use std::collections::HashSet;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Who owns a slot: Reserved → Occupied → Submitted → Completed → Free
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(u8)]
+pub enum SlotState {
+    /// Vacant
+    Free,
+    /// Reserved but not yet taken
+    Reserved,
+    /// Taken and owned by the user
+    Occupied,
+    /// Submitted to the kernel which owns it until completed
+    Submitted,
+    /// Completed by the kernel and owned by the user again
+    Completed,
+}
+
+/// Item stored in the wrapped slab along with its ownership state so that the
+/// state is looked up and moved together with the item
+#[derive(Clone, Debug)]
+pub struct Owned<T> {
+    item: T,
+    state: SlotState,
+    // position in the submitted slots while Submitted
+    submitted_at: usize,
+}
+
+impl<T> Owned<T> {
+    #[inline]
+    fn new(item: T) -> Self {
+        Self {
+            item,
+            state: SlotState::Occupied,
+            submitted_at: 0,
+        }
+    }
+}
+
+/// Wraps any Slabbable of Owned items tracking the ownership of each slot so
+/// that a slot submitted to the kernel can't be accessed or freed whilst the
+/// kernel may hold a raw pointer to it. Access to a Submitted slot fails with
+/// KernelOwned and any other transition not in the SlotState order with
+/// InvalidTransition. A Completed slot may be submitted again.
+#[derive(Debug)]
+pub struct OwnershipSlab<S> {
+    inner: S,
+    // reservations hold no item to keep the state in
+    reserved: HashSet<usize>,
+    submitted: Vec<usize>,
+}
+
+impl<S> OwnershipSlab<S> {
+    /// Track the ownership of the slots in the given slab
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            reserved: HashSet::new(),
+            submitted: Vec::new(),
+        }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// Slots submitted to the kernel
+    pub fn submitted(&self) -> usize {
+        self.submitted.len()
+    }
+    /// State of the given slot
+    pub fn state<T>(&self, slot: usize) -> SlotState
+    where
+        S: Slabbable<S, Owned<T>, Error = SlabbableError>,
+    {
+        if self.reserved.contains(&slot) {
+            return SlotState::Reserved;
+        }
+        match self.inner.slot_get_ref(slot) {
+            Ok(Some(owned)) => owned.state,
+            _ => SlotState::Free,
+        }
+    }
+    // Move the slot from one of the given states into the next
+    fn _transition<T>(
+        &mut self,
+        slot: usize,
+        from: &[SlotState],
+        to: SlotState,
+    ) -> Result<&mut Owned<T>, SlabbableError>
+    where
+        S: Slabbable<S, Owned<T>, Error = SlabbableError>,
+    {
+        match self.inner.slot_get_mut(slot) {
+            Ok(Some(owned)) if from.contains(&owned.state) => {
+                owned.state = to;
+                Ok(owned)
+            }
+            _ => Err(SlabbableError::InvalidTransition(slot)),
+        }
+    }
+    #[inline]
+    fn _user_owned<T>(owned: &Owned<T>, slot: usize) -> Result<(), SlabbableError> {
+        match owned.state {
+            SlotState::Submitted => Err(SlabbableError::KernelOwned(slot)),
+            _ => Ok(()),
+        }
+    }
+    /// Hand the Occupied or Completed slot over to the kernel
+    pub fn submit<T>(&mut self, slot: usize) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, Owned<T>, Error = SlabbableError>,
+    {
+        let submitted_at = self.submitted.len();
+        let owned = self._transition::<T>(
+            slot,
+            &[SlotState::Occupied, SlotState::Completed],
+            SlotState::Submitted,
+        )?;
+        owned.submitted_at = submitted_at;
+        self.submitted.push(slot);
+        Ok(())
+    }
+    /// The kernel is done with the Submitted slot
+    pub fn complete<T>(&mut self, slot: usize) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, Owned<T>, Error = SlabbableError>,
+    {
+        let at = self
+            ._transition::<T>(slot, &[SlotState::Submitted], SlotState::Completed)?
+            .submitted_at;
+        self.submitted.swap_remove(at);
+        if let Some(&moved) = self.submitted.get(at) {
+            match self.inner.slot_get_mut(moved)? {
+                Some(owned) => owned.submitted_at = at,
+                None => return Err(SlabbableError::Bug("Submitted slot vanished")),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S, T> Slabbable<OwnershipSlab<S>, T> for OwnershipSlab<S>
+where
+    S: Slabbable<S, Owned<T>, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        let slot = self.inner.reserve_next()?;
+        self.reserved.insert(slot.id());
+        Ok(slot)
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        let id = slot.id();
+        if !self.reserved.contains(&id) {
+            return Err(SlabbableError::InvalidTransition(id));
+        }
+        let taken = self.inner.take_reserved_with(slot, Owned::new(with))?;
+        self.reserved.remove(&id);
+        Ok(taken)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        let id = slot.id();
+        if !self.reserved.contains(&id) {
+            return Err(SlabbableError::InvalidTransition(id));
+        }
+        self.inner.cancel_reserved(slot)?;
+        self.reserved.remove(&id);
+        Ok(())
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_next_with(Owned::new(with))
+    }
+    /// See trait - KernelOwned if Submitted.
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        match self.state::<T>(slot) {
+            SlotState::Occupied | SlotState::Completed => {}
+            SlotState::Submitted => return Err(SlabbableError::KernelOwned(slot)),
+            SlotState::Free | SlotState::Reserved => {
+                return Err(SlabbableError::InvalidTransition(slot))
+            }
+        }
+        Ok(self.inner.mark_for_reuse(slot)?.item)
+    }
+    /// See trait - KernelOwned if Submitted.
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        match self.inner.slot_get_mut(slot)? {
+            Some(owned) => {
+                Self::_user_owned(owned, slot)?;
+                Ok(Some(&mut owned.item))
+            }
+            None => Ok(None),
+        }
+    }
+    /// See trait - KernelOwned if Submitted as the kernel may be writing into it.
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        match self.inner.slot_get_ref(slot)? {
+            Some(owned) => {
+                Self::_user_owned(owned, slot)?;
+                Ok(Some(&owned.item))
+            }
+            None => Ok(None),
+        }
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait - refused with KernelOwned while slots are Submitted.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        if let Some(&slot) = self.submitted.first() {
+            return Err(SlabbableError::KernelOwned(slot));
+        }
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - Submitted slots never move and the states move along.
+    fn compact<M, R>(&mut self, mut movable: M, mut relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        self.inner.compact(
+            |slot, owned| owned.state != SlotState::Submitted && movable(slot, &owned.item),
+            |relocation, owned| relocated(relocation, &owned.item),
+        )
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    fn slab() -> OwnershipSlab<TestableSlab<Owned<u8>>> {
+        OwnershipSlab::new(TestableSlab::with_fixed_capacity(4).unwrap())
+    }
+
+    #[test]
+    fn full_lifecycle() {
+        let mut slab = slab();
+        let reserved = slab.reserve_next().unwrap();
+        let id = reserved.id();
+        assert_eq!(slab.state(id), SlotState::Reserved);
+        let key = slab.take_reserved_with(reserved, 1).unwrap();
+        assert_eq!(slab.state(key), SlotState::Occupied);
+        slab.submit(key).unwrap();
+        assert_eq!(slab.state(key), SlotState::Submitted);
+        slab.complete(key).unwrap();
+        assert_eq!(slab.state(key), SlotState::Completed);
+        *slab.slot_get_mut(key).unwrap().unwrap() += 1;
+        // re-submit once completed
+        slab.submit(key).unwrap();
+        slab.complete(key).unwrap();
+        assert_eq!(slab.mark_for_reuse(key).unwrap(), 2);
+        assert_eq!(slab.state(key), SlotState::Free);
+    }
+
+    #[test]
+    fn submitted_is_kernel_owned() {
+        let mut slab = slab();
+        let key = slab.take_next_with(1).unwrap();
+        slab.submit(key).unwrap();
+        assert_eq!(
+            slab.slot_get_mut(key),
+            Err(SlabbableError::KernelOwned(key))
+        );
+        assert_eq!(
+            slab.slot_get_ref(key),
+            Err(SlabbableError::KernelOwned(key))
+        );
+        assert_eq!(
+            slab.mark_for_reuse(key),
+            Err(SlabbableError::KernelOwned(key))
+        );
+        assert_eq!(
+            slab.submit(key),
+            Err(SlabbableError::InvalidTransition(key))
+        );
+        assert_eq!(
+            slab.try_resize_fixed_capacity(8, ResizeMode::NoAddressesHeld),
+            Err(SlabbableError::KernelOwned(key))
+        );
+        assert_eq!(slab.state(key), SlotState::Submitted);
+    }
+
+    #[test]
+    fn completions_in_any_order() {
+        let mut slab = slab();
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        for key in &keys {
+            slab.submit(*key).unwrap();
+        }
+        assert_eq!(slab.submitted(), 3);
+        slab.complete(keys[0]).unwrap();
+        slab.complete(keys[2]).unwrap();
+        assert_eq!(
+            slab.try_resize_fixed_capacity(8, ResizeMode::NoAddressesHeld),
+            Err(SlabbableError::KernelOwned(keys[1]))
+        );
+        slab.complete(keys[1]).unwrap();
+        assert_eq!(slab.submitted(), 0);
+        slab.try_resize_fixed_capacity(8, ResizeMode::NoAddressesHeld)
+            .unwrap();
+        assert_eq!(slab.slot_get_ref(keys[2]).unwrap(), Some(&2));
+    }
+
+    #[test]
+    fn invalid_transitions() {
+        let mut slab = slab();
+        let free = 3;
+        assert_eq!(
+            slab.submit(free),
+            Err(SlabbableError::InvalidTransition(free))
+        );
+        assert_eq!(
+            slab.complete(free),
+            Err(SlabbableError::InvalidTransition(free))
+        );
+        assert_eq!(
+            slab.mark_for_reuse(free),
+            Err(SlabbableError::InvalidTransition(free))
+        );
+
+        let reserved = slab.reserve_next().unwrap();
+        let id = reserved.id();
+        assert_eq!(slab.submit(id), Err(SlabbableError::InvalidTransition(id)));
+        assert_eq!(
+            slab.complete(id),
+            Err(SlabbableError::InvalidTransition(id))
+        );
+        assert_eq!(
+            slab.mark_for_reuse(id),
+            Err(SlabbableError::InvalidTransition(id))
+        );
+        slab.cancel_reserved(reserved).unwrap();
+        assert_eq!(
+            slab.cancel_reserved(ReservedSlot::issue(id)),
+            Err(SlabbableError::InvalidTransition(id))
+        );
+        assert_eq!(
+            slab.take_reserved_with(ReservedSlot::issue(id), 0),
+            Err(SlabbableError::InvalidTransition(id))
+        );
+
+        let key = slab.take_next_with(1).unwrap();
+        assert_eq!(
+            slab.complete(key),
+            Err(SlabbableError::InvalidTransition(key))
+        );
+        assert_eq!(
+            slab.take_reserved_with(ReservedSlot::issue(key), 0),
+            Err(SlabbableError::InvalidTransition(key))
+        );
+        slab.submit(key).unwrap();
+        slab.complete(key).unwrap();
+        assert_eq!(
+            slab.complete(key),
+            Err(SlabbableError::InvalidTransition(key))
+        );
+        assert_eq!(slab.state(key), SlotState::Completed);
+    }
+}
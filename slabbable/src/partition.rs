@@ -0,0 +1,339 @@
+//! Slabbable per-tenant partitions
+
+use std::collections::HashMap;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Tenant / partition a slot is taken for
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct PartitionId(pub usize);
+
+impl PartitionId {
+    /// Partition of the plain Slabbable methods
+    pub const DEFAULT: Self = Self(0);
+}
+
+/// Occupancy of a partition
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PartitionStats {
+    /// Live + reserved slots the partition may hold, None for no limit
+    pub quota: Option<usize>,
+    /// Slots currently taken
+    pub live: usize,
+    /// Slots currently reserved but not yet taken
+    pub reserved: usize,
+    /// Reservations or takes rejected with QuotaExceeded
+    pub rejected: u64,
+}
+
+impl PartitionStats {
+    #[inline]
+    fn _occupied(&self) -> usize {
+        self.live + self.reserved
+    }
+}
+
+/// Wraps any Slabbable partitioning its one fixed capacity across tenants,
+/// each with its own quota of live + reserved slots. A partition at its quota
+/// is rejected with QuotaExceeded even if the slab has room whilst a full
+/// slab is still AtCapacity. The quotas may add up beyond the capacity.
+#[derive(Debug)]
+pub struct PartitionedSlab<S> {
+    inner: S,
+    default_quota: Option<usize>,
+    partitions: HashMap<PartitionId, PartitionStats>,
+    // partition of each reserved or taken slot
+    owners: HashMap<usize, PartitionId>,
+}
+
+impl<S> PartitionedSlab<S> {
+    /// Partitions without quota unless configured
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            default_quota: None,
+            partitions: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// Quota of the partitions not given one through set_quota()
+    pub fn set_default_quota(&mut self, quota: Option<usize>) {
+        self.default_quota = quota;
+    }
+    /// Set the quota of the given partition. Lowering it below the current
+    /// occupancy only rejects until the partition falls under it.
+    pub fn set_quota(&mut self, partition: PartitionId, quota: Option<usize>) {
+        self._partition(partition).quota = quota;
+    }
+    /// Stats of the given partition if it has been configured or used
+    pub fn partition_stats(&self, partition: PartitionId) -> Option<PartitionStats> {
+        self.partitions.get(&partition).copied()
+    }
+    /// Stats of all partitions configured or used
+    pub fn partitions(&self) -> impl Iterator<Item = (PartitionId, PartitionStats)> + '_ {
+        self.partitions.iter().map(|(id, stats)| (*id, *stats))
+    }
+    /// Partition the given slot is reserved or taken for
+    pub fn partition_of(&self, slot: usize) -> Option<PartitionId> {
+        self.owners.get(&slot).copied()
+    }
+    fn _partition(&mut self, partition: PartitionId) -> &mut PartitionStats {
+        let default_quota = self.default_quota;
+        self.partitions
+            .entry(partition)
+            .or_insert_with(|| PartitionStats {
+                quota: default_quota,
+                ..PartitionStats::default()
+            })
+    }
+    fn _admit(&mut self, partition: PartitionId) -> Result<(), SlabbableError> {
+        let stats = self._partition(partition);
+        match stats.quota {
+            Some(quota) if stats._occupied() >= quota => {
+                stats.rejected += 1;
+                Err(SlabbableError::QuotaExceeded(partition.0))
+            }
+            _ => Ok(()),
+        }
+    }
+    fn _release(&mut self, slot: usize) -> Option<&mut PartitionStats> {
+        let partition = self.owners.remove(&slot)?;
+        self.partitions.get_mut(&partition)
+    }
+    /// Reserve the next free slot for the given partition
+    pub fn reserve_next_in<T>(
+        &mut self,
+        partition: PartitionId,
+    ) -> Result<ReservedSlot, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self._admit(partition)?;
+        let slot = self.inner.reserve_next()?;
+        self._partition(partition).reserved += 1;
+        self.owners.insert(slot.id(), partition);
+        Ok(slot)
+    }
+    /// Take the next free slot for the given partition
+    pub fn take_next_in<T>(
+        &mut self,
+        partition: PartitionId,
+        with: T,
+    ) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self._admit(partition)?;
+        let slot = self.inner.take_next_with(with)?;
+        self._partition(partition).live += 1;
+        self.owners.insert(slot, partition);
+        Ok(slot)
+    }
+}
+
+impl<S, T> Slabbable<PartitionedSlab<S>, T> for PartitionedSlab<S>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait - for the DEFAULT partition.
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self.reserve_next_in(PartitionId::DEFAULT)
+    }
+    /// See trait - for the partition it was reserved for.
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        let id = slot.id();
+        let taken = self.inner.take_reserved_with(slot, with)?;
+        let partition = match self.owners.remove(&id) {
+            Some(partition) => partition,
+            None => return Err(SlabbableError::Bug("Reservation without partition")),
+        };
+        let stats = self._partition(partition);
+        stats.reserved -= 1;
+        stats.live += 1;
+        self.owners.insert(taken, partition);
+        Ok(taken)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        let id = slot.id();
+        self.inner.cancel_reserved(slot)?;
+        if let Some(stats) = self._release(id) {
+            stats.reserved -= 1;
+        }
+        Ok(())
+    }
+    /// See trait - for the DEFAULT partition.
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self.take_next_in(PartitionId::DEFAULT, with)
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        let item = self.inner.mark_for_reuse(slot)?;
+        if let Some(stats) = self._release(slot) {
+            stats.live -= 1;
+        }
+        Ok(item)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait - shared by all the partitions.
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - the partitions follow the relocated items.
+    fn compact<M, R>(&mut self, movable: M, mut relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        let owners = &mut self.owners;
+        self.inner.compact(movable, |relocation, item| {
+            if let Some(partition) = owners.remove(&relocation.old_key) {
+                owners.insert(relocation.new_key, partition);
+            }
+            relocated(relocation, item)
+        })
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn quota_per_partition() {
+        let (a, b) = (PartitionId(1), PartitionId(2));
+        let mut slab = PartitionedSlab::new(TestableSlab::<u8>::with_fixed_capacity(4).unwrap());
+        slab.set_default_quota(Some(3));
+        slab.set_quota(a, Some(2));
+
+        let reserved = slab.reserve_next_in(a).unwrap();
+        let taken = slab.take_next_in(a, 1).unwrap();
+        assert_eq!(
+            slab.take_next_in(a, 2),
+            Err(SlabbableError::QuotaExceeded(1))
+        );
+        assert_eq!(slab.partition_of(taken), Some(a));
+
+        slab.take_next_in(b, 3).unwrap();
+        slab.take_next_in(b, 4).unwrap();
+        assert_eq!(slab.take_next_in(b, 5), Err(SlabbableError::AtCapacity(4)));
+
+        let in_a = slab.take_reserved_with(reserved, 0).unwrap();
+        assert_eq!(slab.partition_of(in_a), Some(a));
+        assert_eq!(
+            slab.partition_stats(a),
+            Some(PartitionStats {
+                quota: Some(2),
+                live: 2,
+                reserved: 0,
+                rejected: 1,
+            })
+        );
+        slab.mark_for_reuse(taken).unwrap();
+        assert_eq!(slab.partition_stats(a).unwrap().live, 1);
+        assert_eq!(slab.partition_of(taken), None);
+        assert_eq!(slab.partition_stats(b).unwrap().quota, Some(3));
+        assert_eq!(slab.partitions().count(), 2);
+    }
+
+    #[test]
+    fn cancel_releases_quota() {
+        let mut slab = PartitionedSlab::new(TestableSlab::<u8>::with_fixed_capacity(4).unwrap());
+        slab.set_quota(PartitionId::DEFAULT, Some(1));
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(slab.reserve_next(), Err(SlabbableError::QuotaExceeded(0)));
+        slab.cancel_reserved(reserved).unwrap();
+        slab.take_next_with(1).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! Slabbable reference-counted slots
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Wraps any Slabbable counting the outstanding references upon each slot e.g.
+/// a multishot accept / recv or a linked SQE chain pointing at one slot. The
+/// slot is freed once the owner has released it and the last pin is dropped.
+/// The counters are single-threaded.
+#[derive(Debug)]
+pub struct PinnedSlab<S> {
+    inner: S,
+    // absent = unpinned
+    pins: HashMap<usize, usize>,
+    // pinned slots the owner has released
+    released: HashSet<usize>,
+}
+
+impl<S> PinnedSlab<S> {
+    /// Nothing pinned in the given slab
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            pins: HashMap::new(),
+            released: HashSet::new(),
+        }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// Outstanding pins upon the slot
+    pub fn pin_count(&self, slot: usize) -> usize {
+        self.pins.get(&slot).copied().unwrap_or(0)
+    }
+    /// Whether the owner has released the still pinned slot
+    pub fn is_released(&self, slot: usize) -> bool {
+        self.released.contains(&slot)
+    }
+    /// Add a reference upon the taken slot. Returns the pin count.
+    pub fn pin<T>(&mut self, slot: usize) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        if self.is_released(slot) || self.inner.slot_get_ref(slot)?.is_none() {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        let count = self.pins.entry(slot).or_insert(0);
+        *count += 1;
+        Ok(*count)
+    }
+    /// Drop a reference upon the slot. The item is returned when this was the
+    /// last pin of a slot the owner has released as the slot is then freed.
+    pub fn unpin<T>(&mut self, slot: usize) -> Result<Option<T>, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        let count = match self.pins.get_mut(&slot) {
+            Some(count) => count,
+            None => return Err(SlabbableError::InvalidIndex(slot)),
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(None);
+        }
+        self.pins.remove(&slot);
+        match self.released.remove(&slot) {
+            true => self.inner.mark_for_reuse(slot).map(Some),
+            false => Ok(None),
+        }
+    }
+    /// The owner is done with the slot. Freed now returning the item if not
+    /// pinned, otherwise freed upon the last unpin().
+    pub fn release<T>(&mut self, slot: usize) -> Result<Option<T>, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        if self.pin_count(slot) == 0 {
+            return self.inner.mark_for_reuse(slot).map(Some);
+        }
+        if !self.released.insert(slot) {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        Ok(None)
+    }
+}
+
+impl<S, T> Slabbable<PinnedSlab<S>, T> for PinnedSlab<S>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self.inner.reserve_next()
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_reserved_with(slot, with)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self.inner.cancel_reserved(slot)
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_next_with(with)
+    }
+    /// See trait - Pinned while the pin count is non-zero, see release().
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        if self.pin_count(slot) > 0 {
+            return Err(SlabbableError::Pinned(slot));
+        }
+        self.inner.mark_for_reuse(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait - the released but pinned slots are held.
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait - refused with NotEmpty while slots are pinned.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        if !self.pins.is_empty() {
+            return Err(SlabbableError::NotEmpty(self.pins.len()));
+        }
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - pinned slots never move.
+    fn compact<M, R>(&mut self, mut movable: M, relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        let pins = &self.pins;
+        self.inner.compact(
+            |slot, item| !pins.contains_key(&slot) && movable(slot, item),
+            relocated,
+        )
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn freed_upon_last_unpin_after_release() {
+        let mut slab = PinnedSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        let key = slab.take_next_with(1).unwrap();
+        assert_eq!(slab.pin(key), Ok(1));
+        assert_eq!(slab.pin(key), Ok(2));
+        assert_eq!(slab.mark_for_reuse(key), Err(SlabbableError::Pinned(key)));
+
+        // not yet released
+        assert_eq!(slab.unpin(key), Ok(None));
+        assert_eq!(slab.release(key), Ok(None));
+        assert!(slab.is_released(key));
+        assert_eq!(slab.release(key), Err(SlabbableError::InvalidIndex(key)));
+        assert_eq!(slab.pin(key), Err(SlabbableError::InvalidIndex(key)));
+        assert_eq!(slab.remaining(), Some(1));
+
+        assert_eq!(slab.unpin(key), Ok(Some(1)));
+        assert_eq!(slab.pin_count(key), 0);
+        assert_eq!(slab.unpin(key), Err(SlabbableError::InvalidIndex(key)));
+        assert_eq!(slab.remaining(), Some(2));
+    }
+
+    #[test]
+    fn unpinned_is_freed_as_usual() {
+        let mut slab = PinnedSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        let a = slab.take_next_with(1).unwrap();
+        let b = slab.take_next_with(2).unwrap();
+        assert_eq!(slab.pin(a), Ok(1));
+        assert_eq!(slab.unpin(a), Ok(None));
+        assert_eq!(slab.mark_for_reuse(a), Ok(1));
+        assert_eq!(slab.release(b), Ok(Some(2)));
+        assert_eq!(slab.pin(b), Err(SlabbableError::InvalidIndex(b)));
+    }
+}
@@ -0,0 +1,546 @@
+//! Memory pressure driven reaping (Linux)
+//!
+//! Reads the memory Pressure Stall Information from `/proc/pressure/memory` and
+//! the cgroup v2 `memory.current`, `memory.max` and `memory.events` and upon
+//! crossing the configured thresholds runs the registered reapers.
+//!
+//! The slabs are registered as closures since Slabbable is not object safe, e.g.
+//! with the slab shared within the thread as `Rc<RefCell<_>>`:
+//!
+//! ```ignore
+//! monitor.register(move |budget| conns.borrow_mut().reap_bounded(budget));
+//! ```
+
+use std::boxed::Box;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::vec::Vec;
+
+use crate::{ReapBudget, ReapProgress};
+
+/// Where the pressure information is read from. Injectable for testing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PressurePaths {
+    /// PSI memory file, typically `/proc/pressure/memory`
+    pub psi_memory: PathBuf,
+    /// cgroup v2 directory holding `memory.current`, `memory.max`, `memory.events`
+    pub cgroup: PathBuf,
+}
+
+impl PressurePaths {
+    /// Provided paths
+    pub fn new<P: Into<PathBuf>, C: Into<PathBuf>>(psi_memory: P, cgroup: C) -> Self {
+        Self {
+            psi_memory: psi_memory.into(),
+            cgroup: cgroup.into(),
+        }
+    }
+    /// The system paths with the cgroup of the current process resolved via
+    /// `/proc/self/cgroup` falling back to the `/sys/fs/cgroup` root.
+    pub fn system() -> Self {
+        let root = PathBuf::from("/sys/fs/cgroup");
+        let cgroup = match fs::read_to_string("/proc/self/cgroup") {
+            Ok(s) => match s.lines().find_map(|l| l.strip_prefix("0::")) {
+                Some(rel) => root.join(rel.trim_start_matches('/')),
+                None => root,
+            },
+            Err(_) => root,
+        };
+        Self::new("/proc/pressure/memory", cgroup)
+    }
+}
+
+/// One line of PSI averages in percent and total stall time in microseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PsiLine {
+    /// 10 second average
+    pub avg10: f32,
+    /// 60 second average
+    pub avg60: f32,
+    /// 300 second average
+    pub avg300: f32,
+    /// Total stall time
+    pub total: u64,
+}
+
+/// PSI memory pressure
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Psi {
+    /// Some tasks stalled on memory
+    pub some: PsiLine,
+    /// All non-idle tasks stalled on memory
+    pub full: Option<PsiLine>,
+}
+
+/// cgroup v2 `memory.events` counters
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CgroupEvents {
+    /// Reclaimed below memory.low
+    pub low: u64,
+    /// Throttled over memory.high
+    pub high: u64,
+    /// Hit memory.max
+    pub max: u64,
+    /// OOM happened
+    pub oom: u64,
+    /// OOM killed
+    pub oom_kill: u64,
+}
+
+/// cgroup v2 memory usage
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CgroupMemory {
+    /// memory.current in bytes
+    pub current: u64,
+    /// memory.max in bytes, None when unlimited
+    pub max: Option<u64>,
+    /// memory.events
+    pub events: CgroupEvents,
+}
+
+/// Snapshot of the memory pressure. Sources not available are None.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryPressure {
+    /// From the PSI file
+    pub psi: Option<Psi>,
+    /// From the cgroup directory
+    pub cgroup: Option<CgroupMemory>,
+}
+
+/// Thresholds any of which crossed triggers reaping. None disables the check.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PressureThresholds {
+    /// PSI some avg10 at or above this percent
+    pub psi_some_avg10: Option<f32>,
+    /// PSI full avg10 at or above this percent
+    pub psi_full_avg10: Option<f32>,
+    /// memory.current at or above this percent of memory.max
+    pub cgroup_usage_percent: Option<u64>,
+    /// Any of the memory.events high, max or oom counters increased since last poll
+    pub cgroup_events: bool,
+}
+
+impl Default for PressureThresholds {
+    fn default() -> Self {
+        Self {
+            psi_some_avg10: Some(10.0),
+            psi_full_avg10: Some(5.0),
+            cgroup_usage_percent: Some(90),
+            cgroup_events: true,
+        }
+    }
+}
+
+/// The threshold that triggered reaping
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PressureTrigger {
+    /// PSI some avg10
+    PsiSome(f32),
+    /// PSI full avg10
+    PsiFull(f32),
+    /// memory.current over memory.max
+    CgroupUsage {
+        /// memory.current
+        current: u64,
+        /// memory.max
+        max: u64,
+    },
+    /// memory.events counters increased
+    CgroupEvents,
+}
+
+/// Outcome of a poll()
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PressurePoll {
+    /// The pressure read
+    pub pressure: MemoryPressure,
+    /// Threshold crossed, None when under all thresholds
+    pub trigger: Option<PressureTrigger>,
+    /// Slots reaped across the reapers
+    pub reaped: usize,
+    /// Some reaper has budgeted work remaining
+    pub pending: bool,
+}
+
+/// Reaper registered with the PressureMonitor
+pub type Reaper<'r> = Box<dyn FnMut(ReapBudget) -> ReapProgress + 'r>;
+
+/// Polls the memory pressure and runs the registered reapers upon thresholds crossed.
+pub struct PressureMonitor<'r> {
+    paths: PressurePaths,
+    thresholds: PressureThresholds,
+    budget: ReapBudget,
+    last_events: Option<CgroupEvents>,
+    reapers: Vec<Reaper<'r>>,
+}
+
+impl core::fmt::Debug for PressureMonitor<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PressureMonitor")
+            .field("paths", &self.paths)
+            .field("thresholds", &self.thresholds)
+            .field("budget", &self.budget)
+            .field("last_events", &self.last_events)
+            .field("reapers", &self.reapers.len())
+            .finish()
+    }
+}
+
+impl<'r> PressureMonitor<'r> {
+    /// Monitor reading the given paths giving each reaper the budget per poll.
+    /// `ReapBudget::Slots(usize::MAX)` reaps everything at once.
+    pub fn new(paths: PressurePaths, thresholds: PressureThresholds, budget: ReapBudget) -> Self {
+        Self {
+            paths,
+            thresholds,
+            budget,
+            last_events: None,
+            reapers: Vec::new(),
+        }
+    }
+    /// Register a reaper, typically calling reap_bounded() of a slab.
+    pub fn register<F>(&mut self, reaper: F)
+    where
+        F: FnMut(ReapBudget) -> ReapProgress + 'r,
+    {
+        self.reapers.push(Box::new(reaper));
+    }
+    /// Read the current pressure. Files missing, unsupported e.g. PSI disabled
+    /// in the kernel or not permitted make the source None.
+    pub fn read(&self) -> io::Result<MemoryPressure> {
+        let psi = match read_optional(&self.paths.psi_memory)? {
+            Some(s) => Some(parse_psi(&s)?),
+            None => None,
+        };
+        let cgroup = match read_optional(&self.paths.cgroup.join("memory.current"))? {
+            Some(current) => {
+                let max = read_optional(&self.paths.cgroup.join("memory.max"))?;
+                let events = read_optional(&self.paths.cgroup.join("memory.events"))?;
+                Some(CgroupMemory {
+                    current: parse_u64(current.trim())?,
+                    max: match max.as_deref().map(str::trim) {
+                        None | Some("max") => None,
+                        Some(max) => Some(parse_u64(max)?),
+                    },
+                    events: match events {
+                        Some(events) => parse_events(&events)?,
+                        None => CgroupEvents::default(),
+                    },
+                })
+            }
+            None => None,
+        };
+        Ok(MemoryPressure { psi, cgroup })
+    }
+    /// Read the pressure and run the reapers if any threshold is crossed.
+    pub fn poll(&mut self) -> io::Result<PressurePoll> {
+        let pressure = self.read()?;
+        let trigger = self.evaluate(&pressure);
+        let mut reaped = 0;
+        let mut pending = false;
+        if trigger.is_some() {
+            for reaper in self.reapers.iter_mut() {
+                match reaper(self.budget) {
+                    ReapProgress::Done(r) => reaped += r,
+                    ReapProgress::Pending(r) => {
+                        reaped += r;
+                        pending = true;
+                    }
+                    ReapProgress::Unsupported => {}
+                }
+            }
+        }
+        Ok(PressurePoll {
+            pressure,
+            trigger,
+            reaped,
+            pending,
+        })
+    }
+    fn evaluate(&mut self, pressure: &MemoryPressure) -> Option<PressureTrigger> {
+        let t = &self.thresholds;
+        let mut trigger = None;
+        if let Some(psi) = &pressure.psi {
+            if let Some(some) = t.psi_some_avg10 {
+                if psi.some.avg10 >= some {
+                    trigger = Some(PressureTrigger::PsiSome(psi.some.avg10));
+                }
+            }
+            if let (Some(full), Some(line)) = (t.psi_full_avg10, &psi.full) {
+                if trigger.is_none() && line.avg10 >= full {
+                    trigger = Some(PressureTrigger::PsiFull(line.avg10));
+                }
+            }
+        }
+        if let Some(cgroup) = &pressure.cgroup {
+            if let (Some(percent), Some(max)) = (t.cgroup_usage_percent, cgroup.max) {
+                if trigger.is_none()
+                    && cgroup.current.saturating_mul(100) >= max.saturating_mul(percent)
+                {
+                    trigger = Some(PressureTrigger::CgroupUsage {
+                        current: cgroup.current,
+                        max,
+                    });
+                }
+            }
+            // Always track the counters so a later crossing is relative to now.
+            let last = self.last_events.replace(cgroup.events);
+            if let (true, Some(last)) = (t.cgroup_events, last) {
+                let e = &cgroup.events;
+                if trigger.is_none() && (e.high > last.high || e.max > last.max || e.oom > last.oom)
+                {
+                    trigger = Some(PressureTrigger::CgroupEvents);
+                }
+            }
+        }
+        trigger
+    }
+}
+
+// Whether the read error means the source is not available so that one such
+// source doesn't fail the read of the others. PSI disabled in the kernel fails
+// the read of /proc/pressure/* with EOPNOTSUPP which is Unsupported.
+fn is_absent(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::Unsupported | io::ErrorKind::PermissionDenied
+    )
+}
+
+fn read_optional(path: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if is_absent(&e) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn invalid(what: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, what)
+}
+
+fn parse_u64(s: &str) -> io::Result<u64> {
+    s.parse::<u64>()
+        .map_err(|_| invalid("Invalid integer in cgroup memory file"))
+}
+
+fn parse_psi_line(line: &str) -> io::Result<PsiLine> {
+    let mut psi = PsiLine::default();
+    for field in line.split_whitespace().skip(1) {
+        let (k, v) = field.split_once('=').ok_or(invalid("Invalid PSI field"))?;
+        let float = || v.parse::<f32>().map_err(|_| invalid("Invalid PSI average"));
+        match k {
+            "avg10" => psi.avg10 = float()?,
+            "avg60" => psi.avg60 = float()?,
+            "avg300" => psi.avg300 = float()?,
+            "total" => psi.total = v.parse::<u64>().map_err(|_| invalid("Invalid PSI total"))?,
+            _ => {}
+        }
+    }
+    Ok(psi)
+}
+
+fn parse_psi(s: &str) -> io::Result<Psi> {
+    let mut some = None;
+    let mut full = None;
+    for line in s.lines() {
+        if line.starts_with("some ") {
+            some = Some(parse_psi_line(line)?);
+        } else if line.starts_with("full ") {
+            full = Some(parse_psi_line(line)?);
+        }
+    }
+    Ok(Psi {
+        some: some.ok_or(invalid("PSI some line missing"))?,
+        full,
+    })
+}
+
+fn parse_events(s: &str) -> io::Result<CgroupEvents> {
+    let mut events = CgroupEvents::default();
+    for line in s.lines() {
+        let (k, v) = match line.split_once(' ') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let v = parse_u64(v.trim())?;
+        match k {
+            "low" => events.low = v,
+            "high" => events.high = v,
+            "max" => events.max = v,
+            "oom" => events.oom = v,
+            "oom_kill" => events.oom_kill = v,
+            _ => {}
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    struct FakeFiles {
+        dir: PathBuf,
+    }
+
+    impl FakeFiles {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "slabbable-pressure-{}-{}",
+                std::process::id(),
+                name
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            Self { dir }
+        }
+        fn write(&self, file: &str, content: &str) {
+            fs::write(self.dir.join(file), content).unwrap();
+        }
+        fn paths(&self) -> PressurePaths {
+            PressurePaths::new(self.dir.join("psi_memory"), &self.dir)
+        }
+    }
+
+    impl Drop for FakeFiles {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    const PSI_CALM: &str = "some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n\
+                            full avg10=0.00 avg60=0.00 avg300=0.00 total=0\n";
+    const PSI_STALLING: &str = "some avg10=12.50 avg60=3.10 avg300=1.00 total=123456\n\
+                                full avg10=1.00 avg60=0.20 avg300=0.00 total=2345\n";
+    const EVENTS: &str = "low 0\nhigh 0\nmax 0\noom 0\noom_kill 0\noom_group_kill 0\n";
+
+    #[test]
+    fn read_fake_files() {
+        let fake = FakeFiles::new("read");
+        fake.write("psi_memory", PSI_STALLING);
+        fake.write("memory.current", "1048576\n");
+        fake.write("memory.max", "max\n");
+        fake.write("memory.events", EVENTS);
+
+        let monitor = PressureMonitor::new(
+            fake.paths(),
+            PressureThresholds::default(),
+            ReapBudget::Slots(8),
+        );
+        let pressure = monitor.read().unwrap();
+        let psi = pressure.psi.unwrap();
+        assert_eq!(psi.some.avg10, 12.5);
+        assert_eq!(psi.some.total, 123456);
+        assert_eq!(psi.full.unwrap().avg60, 0.2);
+        let cgroup = pressure.cgroup.unwrap();
+        assert_eq!(cgroup.current, 1048576);
+        assert_eq!(cgroup.max, None);
+        assert_eq!(cgroup.events, CgroupEvents::default());
+    }
+
+    #[test]
+    fn missing_sources() {
+        let fake = FakeFiles::new("missing");
+        let monitor = PressureMonitor::new(
+            fake.paths(),
+            PressureThresholds::default(),
+            ReapBudget::Slots(8),
+        );
+        assert_eq!(monitor.read().unwrap(), MemoryPressure::default());
+    }
+
+    #[test]
+    fn unsupported_and_denied_sources_are_absent() {
+        for kind in [
+            io::ErrorKind::NotFound,
+            io::ErrorKind::Unsupported,
+            io::ErrorKind::PermissionDenied,
+        ] {
+            assert!(is_absent(&io::Error::from(kind)));
+        }
+        assert!(!is_absent(&io::Error::from(io::ErrorKind::InvalidData)));
+        assert!(!is_absent(&io::Error::from(io::ErrorKind::Interrupted)));
+    }
+
+    #[test]
+    fn psi_triggers_reapers() {
+        let fake = FakeFiles::new("psi");
+        fake.write("psi_memory", PSI_CALM);
+        let calls = Cell::new(0);
+
+        let mut monitor = PressureMonitor::new(
+            fake.paths(),
+            PressureThresholds::default(),
+            ReapBudget::Slots(8),
+        );
+        monitor.register(|budget| {
+            calls.set(calls.get() + 1);
+            assert_eq!(budget, ReapBudget::Slots(8));
+            ReapProgress::Pending(8)
+        });
+        monitor.register(|_| ReapProgress::Unsupported);
+
+        let poll = monitor.poll().unwrap();
+        assert_eq!(poll.trigger, None);
+        assert_eq!(calls.get(), 0);
+
+        fake.write("psi_memory", PSI_STALLING);
+        let poll = monitor.poll().unwrap();
+        assert_eq!(poll.trigger, Some(PressureTrigger::PsiSome(12.5)));
+        assert_eq!(poll.reaped, 8);
+        assert!(poll.pending);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn cgroup_usage_and_events_trigger() {
+        let fake = FakeFiles::new("cgroup");
+        fake.write("memory.current", "900\n");
+        fake.write("memory.max", "1000\n");
+        fake.write("memory.events", EVENTS);
+
+        let thresholds = PressureThresholds {
+            cgroup_usage_percent: Some(95),
+            ..PressureThresholds::default()
+        };
+        let mut monitor = PressureMonitor::new(fake.paths(), thresholds, ReapBudget::Bytes(64));
+        monitor.register(|_| ReapProgress::Done(3));
+
+        // first read only records the event counters
+        assert_eq!(monitor.poll().unwrap().trigger, None);
+
+        fake.write("memory.events", "low 0\nhigh 2\nmax 0\noom 0\noom_kill 0\n");
+        let poll = monitor.poll().unwrap();
+        assert_eq!(poll.trigger, Some(PressureTrigger::CgroupEvents));
+        assert_eq!(poll.reaped, 3);
+        assert!(!poll.pending);
+        assert_eq!(monitor.poll().unwrap().trigger, None);
+
+        fake.write("memory.current", "960\n");
+        assert_eq!(
+            monitor.poll().unwrap().trigger,
+            Some(PressureTrigger::CgroupUsage {
+                current: 960,
+                max: 1000
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_psi() {
+        let fake = FakeFiles::new("invalid");
+        fake.write("psi_memory", "some avg10=lots\n");
+        let monitor = PressureMonitor::new(
+            fake.paths(),
+            PressureThresholds::default(),
+            ReapBudget::Slots(8),
+        );
+        assert_eq!(
+            monitor.read().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
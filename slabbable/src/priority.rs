@@ -0,0 +1,276 @@
+//! Slabbable headroom for priority classes
+
+use std::collections::BTreeMap;
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Priority class of a reservation or take, higher is more important
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Priority(pub u8);
+
+impl Priority {
+    /// Normal traffic and the class of the plain Slabbable methods
+    pub const NORMAL: Self = Self(0);
+    /// e.g. health checks and admin traffic
+    pub const HIGH: Self = Self(u8::MAX);
+}
+
+/// Wraps any Slabbable keeping headroom free per priority class so that a
+/// class is rejected with AtCapacity while the headroom is all that's left,
+/// leaving it to the classes with less headroom. Classes without configured
+/// headroom may fill the slab. The plain Slabbable methods take as NORMAL.
+#[derive(Debug)]
+pub struct PrioritySlab<S> {
+    inner: S,
+    // percent of the capacity each class must leave free
+    headroom: BTreeMap<Priority, usize>,
+}
+
+impl<S> PrioritySlab<S> {
+    /// No headroom configured for the given slab
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            headroom: BTreeMap::new(),
+        }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// Headroom in slots the given class must leave free
+    pub fn headroom<T>(&self, priority: Priority) -> usize
+    where
+        S: Slabbable<S, T>,
+    {
+        let percent = self.headroom.get(&priority).copied().unwrap_or(0);
+        (self.inner.capacity() * percent).div_ceil(100)
+    }
+    /// Keep the given percent of the capacity free from the given class
+    pub fn set_headroom(&mut self, priority: Priority, percent: usize) {
+        match percent.min(100) {
+            0 => self.headroom.remove(&priority),
+            percent => self.headroom.insert(priority, percent),
+        };
+    }
+    /// Remaining capacity available to the given class
+    pub fn remaining_for<T>(&self, priority: Priority) -> Option<usize>
+    where
+        S: Slabbable<S, T>,
+    {
+        let free = self.inner.remaining().unwrap_or(0);
+        match free.saturating_sub(self.headroom(priority)) {
+            0 => None,
+            rem => Some(rem),
+        }
+    }
+    fn _admit<T>(&self, priority: Priority) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, T>,
+    {
+        if self.remaining_for(priority).is_none() {
+            let cap = self.inner.capacity();
+            return Err(SlabbableError::AtCapacity(
+                cap.saturating_sub(self.headroom(priority)),
+            ));
+        }
+        Ok(())
+    }
+    /// Reserve the next free slot as the given class
+    pub fn reserve_next_with_priority<T>(
+        &mut self,
+        priority: Priority,
+    ) -> Result<ReservedSlot, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self._admit(priority)?;
+        self.inner.reserve_next()
+    }
+    /// Take the next free slot as the given class
+    pub fn take_next_with_priority<T>(
+        &mut self,
+        with: T,
+        priority: Priority,
+    ) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        self._admit(priority)?;
+        self.inner.take_next_with(with)
+    }
+}
+
+impl<S, T> Slabbable<PrioritySlab<S>, T> for PrioritySlab<S>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait - as NORMAL priority.
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self.reserve_next_with_priority(Priority::NORMAL)
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_reserved_with(slot, with)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self.inner.cancel_reserved(slot)
+    }
+    /// See trait - as NORMAL priority.
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self.take_next_with_priority(with, Priority::NORMAL)
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        self.inner.mark_for_reuse(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait - regardless of class, see remaining_for().
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait - the headroom is kept as percent of the new capacity.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait
+    fn compact<M, R>(&mut self, movable: M, relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        self.inner.compact(movable, relocated)
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn headroom_per_class() {
+        let mut slab = PrioritySlab::new(TestableSlab::<u8>::with_fixed_capacity(20).unwrap());
+        slab.set_headroom(Priority::NORMAL, 10);
+        slab.set_headroom(Priority(1), 5);
+        assert_eq!(slab.headroom(Priority::NORMAL), 2);
+        assert_eq!(slab.headroom(Priority(1)), 1);
+        assert_eq!(slab.headroom(Priority::HIGH), 0);
+
+        for i in 0..18 {
+            slab.take_next_with(i).unwrap();
+        }
+        assert_eq!(slab.remaining(), Some(2));
+        assert_eq!(slab.remaining_for(Priority::NORMAL), None);
+        assert_eq!(slab.take_next_with(18), Err(SlabbableError::AtCapacity(18)));
+
+        assert_eq!(slab.remaining_for(Priority(1)), Some(1));
+        let reserved = slab.reserve_next_with_priority(Priority(1)).unwrap();
+        assert_eq!(
+            slab.take_next_with_priority(19, Priority(1)),
+            Err(SlabbableError::AtCapacity(19))
+        );
+
+        assert_eq!(slab.remaining_for(Priority::HIGH), Some(1));
+        slab.take_next_with_priority(19, Priority::HIGH).unwrap();
+        assert_eq!(slab.remaining(), None);
+
+        slab.cancel_reserved(reserved).unwrap();
+        assert_eq!(slab.remaining_for(Priority(1)), None);
+        assert_eq!(slab.remaining_for(Priority::HIGH), Some(1));
+    }
+
+    #[test]
+    fn headroom_follows_resize() {
+        let mut slab = PrioritySlab::new(TestableSlab::<u8>::with_fixed_capacity(10).unwrap());
+        slab.set_headroom(Priority::NORMAL, 20);
+        assert_eq!(slab.headroom(Priority::NORMAL), 2);
+        slab.try_resize_fixed_capacity(20, ResizeMode::IfEmpty)
+            .unwrap();
+        assert_eq!(slab.headroom(Priority::NORMAL), 4);
+        assert_eq!(slab.remaining_for(Priority::NORMAL), Some(16));
+    }
+}
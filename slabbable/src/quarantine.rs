@@ -0,0 +1,112 @@
+//! Slabbable reuse quarantine
+
+use alloc::collections::VecDeque;
+
+/// Delay before a freed slot may be handed out again so that a late completion
+/// still carrying the old key can't alias a new item. The slot is released
+/// once either condition is met, usize::MAX disables the condition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quarantine {
+    /// At least this many other frees must follow
+    pub frees: usize,
+    /// At least this many operations (reserve, take, cancel or free) must follow
+    pub ops: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Held {
+    slot: usize,
+    frees: u64,
+    ops: u64,
+}
+
+/// FIFO of the quarantined slots for the implementations that re-use freed
+/// keys. The implementation keeps the slot occupied until pop_due() hands it back.
+#[derive(Debug)]
+pub struct QuarantineQueue {
+    quarantine: Quarantine,
+    held: VecDeque<Held>,
+    frees: u64,
+    ops: u64,
+}
+
+impl QuarantineQueue {
+    /// New queue holding the slots as per the given quarantine
+    pub fn new(quarantine: Quarantine) -> Self {
+        Self {
+            quarantine,
+            held: VecDeque::new(),
+            frees: 0,
+            ops: 0,
+        }
+    }
+    /// The quarantine evaluated
+    pub fn quarantine(&self) -> &Quarantine {
+        &self.quarantine
+    }
+    /// Change the quarantine keeping the slots held
+    pub fn set_quarantine(&mut self, quarantine: Quarantine) {
+        self.quarantine = quarantine;
+    }
+    /// Slots currently held
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+    /// Whether no slots are held
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+    /// Record an operation upon the slab
+    #[inline]
+    pub fn on_op(&mut self) {
+        self.ops += 1;
+    }
+    /// Hold the freed slot
+    #[inline]
+    pub fn on_free(&mut self, slot: usize) {
+        self.frees += 1;
+        self.held.push_back(Held {
+            slot,
+            frees: self.frees,
+            ops: self.ops,
+        });
+    }
+    /// The slot held the longest if it has served the quarantine
+    #[inline]
+    pub fn pop_due(&mut self) -> Option<usize> {
+        let oldest = self.held.front()?;
+        let frees_since = self.frees - oldest.frees;
+        let ops_since = self.ops - oldest.ops;
+        if frees_since < self.quarantine.frees as u64 && ops_since < self.quarantine.ops as u64 {
+            return None;
+        }
+        self.held.pop_front().map(|held| held.slot)
+    }
+    /// The slot held the longest regardless of the quarantine e.g. when lifted
+    pub fn pop_any(&mut self) -> Option<usize> {
+        self.held.pop_front().map(|held| held.slot)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn released_by_frees_or_ops() {
+        let mut queue = QuarantineQueue::new(Quarantine { frees: 2, ops: 5 });
+        queue.on_free(7);
+        queue.on_free(8);
+        assert_eq!(queue.pop_due(), None);
+        queue.on_free(9);
+        assert_eq!(queue.pop_due(), Some(7));
+        assert_eq!(queue.pop_due(), None);
+
+        for _ in 0..5 {
+            queue.on_op();
+        }
+        assert_eq!(queue.pop_due(), Some(8));
+        assert_eq!(queue.pop_due(), Some(9));
+        assert!(queue.is_empty());
+    }
+}
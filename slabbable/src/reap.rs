@@ -0,0 +1,251 @@
+//! Slabbable reaping
+
+#[cfg(feature = "std")]
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+/// Amount of work a single reap_bounded() call may do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReapBudget {
+    /// Reclaim at most this many slots
+    Slots(usize),
+    /// Reclaim at most this many bytes
+    Bytes(usize),
+}
+
+impl ReapBudget {
+    /// The budget in slots given the bytes each slot takes.
+    /// Always at least one slot so every call makes progress.
+    pub fn as_slots(&self, slot_bytes: usize) -> usize {
+        let slots = match self {
+            Self::Slots(s) => *s,
+            Self::Bytes(b) => b / slot_bytes.max(1),
+        };
+        slots.max(1)
+    }
+}
+
+/// Outcome of a reap_bounded() call
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReapProgress {
+    /// Reaping finished having reclaimed the given slots in this call
+    Done(usize),
+    /// Budget ran out having reclaimed the given slots; call again to continue
+    Pending(usize),
+    /// The implementation does not reap
+    Unsupported,
+}
+
+/// What a slab does once its ReapPolicy deems a reap due.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReapAction {
+    /// Only signal through reap_due() and leave reaping to the caller
+    Signal,
+    /// Run reap_bounded() with the budget upon each mark_for_reuse until done
+    Bounded(ReapBudget),
+}
+
+/// When a reap is worthwhile: occupancy has stayed at or below the given percent
+/// of the peak occupancy (since creation or the last reap) for sustain_ops
+/// mark_for_reuse operations or for the sustain_for duration whichever is first.
+/// The duration needs the std feature for the clock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReapPolicy {
+    /// Occupancy at or below this percent of the peak is low
+    pub below_percent_of_peak: usize,
+    /// Low occupancy lasting this many mark_for_reuse operations makes reap due
+    pub sustain_ops: usize,
+    /// Low occupancy lasting this long makes reap due
+    #[cfg(feature = "std")]
+    pub sustain_for: Option<Duration>,
+    /// What to do once reap is due
+    pub action: ReapAction,
+}
+
+impl Default for ReapPolicy {
+    fn default() -> Self {
+        Self {
+            below_percent_of_peak: 25,
+            sustain_ops: 1024,
+            #[cfg(feature = "std")]
+            sustain_for: None,
+            action: ReapAction::Signal,
+        }
+    }
+}
+
+/// Evaluates a ReapPolicy from the occupancy the implementation reports upon
+/// taking and freeing slots. Implementations embed this to provide reap_due().
+#[derive(Clone, Debug)]
+pub struct ReapTracker {
+    policy: ReapPolicy,
+    peak: usize,
+    low_ops: usize,
+    #[cfg(feature = "std")]
+    low_since: Option<Instant>,
+    due: bool,
+}
+
+impl ReapTracker {
+    /// New tracker evaluating the given policy
+    pub fn new(policy: ReapPolicy) -> Self {
+        Self {
+            policy,
+            peak: 0,
+            low_ops: 0,
+            #[cfg(feature = "std")]
+            low_since: None,
+            due: false,
+        }
+    }
+    /// The policy evaluated
+    pub fn policy(&self) -> &ReapPolicy {
+        &self.policy
+    }
+    /// Peak occupancy since creation or the last reap
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+    /// Whether the policy deems reaping worthwhile
+    pub fn is_due(&self) -> bool {
+        self.due
+    }
+    #[inline]
+    fn _is_low(&self, occupied: usize) -> bool {
+        self.peak > 0 && occupied * 100 <= self.peak * self.policy.below_percent_of_peak
+    }
+    /// Record a slot taken leaving occupied slots.
+    #[inline]
+    pub fn on_take(&mut self, occupied: usize) {
+        if occupied > self.peak {
+            self.peak = occupied;
+        }
+        if self.low_ops > 0 && !self._is_low(occupied) {
+            self.low_ops = 0;
+            #[cfg(feature = "std")]
+            {
+                self.low_since = None;
+            }
+            self.due = false;
+        }
+    }
+    // Count the free towards sustain_ops, Some if that decided whether due
+    #[inline]
+    fn _count_free(&mut self, occupied: usize) -> Option<bool> {
+        if self.due {
+            return Some(true);
+        }
+        if !self._is_low(occupied) {
+            return Some(false);
+        }
+        self.low_ops += 1;
+        if self.low_ops >= self.policy.sustain_ops {
+            self.due = true;
+            return Some(true);
+        }
+        None
+    }
+    // Low occupancy for sustain_for makes reap due, now is only read if needed
+    #[cfg(feature = "std")]
+    #[inline]
+    fn _sustained(&mut self, now: impl FnOnce() -> Instant) -> bool {
+        if let Some(sustain_for) = self.policy.sustain_for {
+            match self.low_since {
+                None => self.low_since = Some(now()),
+                Some(since) => self.due = now().saturating_duration_since(since) >= sustain_for,
+            }
+        }
+        self.due
+    }
+    /// Record a slot freed leaving occupied slots. Returns whether reap is due.
+    #[inline]
+    pub fn on_free(&mut self, occupied: usize) -> bool {
+        match self._count_free(occupied) {
+            Some(due) => due,
+            #[cfg(feature = "std")]
+            None => self._sustained(Instant::now),
+            #[cfg(not(feature = "std"))]
+            None => false,
+        }
+    }
+    /// Record a slot freed at the given instant e.g. from a clock of the caller's.
+    /// Returns whether reap is due.
+    #[cfg(feature = "std")]
+    #[inline]
+    pub fn on_free_at(&mut self, occupied: usize, now: Instant) -> bool {
+        match self._count_free(occupied) {
+            Some(due) => due,
+            None => self._sustained(|| now),
+        }
+    }
+    /// Record that reaping finished with occupied slots left.
+    pub fn reaped(&mut self, occupied: usize) {
+        self.peak = occupied;
+        self.low_ops = 0;
+        #[cfg(feature = "std")]
+        {
+            self.low_since = None;
+        }
+        self.due = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn due_after_sustained_low_ops() {
+        let mut tracker = ReapTracker::new(ReapPolicy {
+            sustain_ops: 2,
+            ..ReapPolicy::default()
+        });
+        for occupied in 1..=8 {
+            tracker.on_take(occupied);
+        }
+        assert_eq!(tracker.peak(), 8);
+        // 3 of 8 is above 25%
+        assert!(!tracker.on_free(3));
+        assert!(!tracker.on_free(2));
+        // back up above the threshold resets the hysteresis
+        tracker.on_take(3);
+        assert!(!tracker.on_free(2));
+        assert!(tracker.on_free(1));
+        assert!(tracker.is_due());
+
+        tracker.reaped(1);
+        assert!(!tracker.is_due());
+        assert_eq!(tracker.peak(), 1);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn due_after_sustained_low_time() {
+        let mut tracker = ReapTracker::new(ReapPolicy {
+            sustain_ops: usize::MAX,
+            sustain_for: Some(Duration::from_secs(10)),
+            ..ReapPolicy::default()
+        });
+        for occupied in 1..=4 {
+            tracker.on_take(occupied);
+        }
+        let start = Instant::now();
+        assert!(!tracker.on_free_at(1, start));
+        assert!(!tracker.on_free_at(1, start + Duration::from_secs(9)));
+        // back up above the threshold restarts the duration
+        tracker.on_take(4);
+        assert!(!tracker.on_free_at(1, start + Duration::from_secs(10)));
+        assert!(!tracker.on_free_at(1, start + Duration::from_secs(19)));
+        assert!(tracker.on_free_at(0, start + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn never_due_without_peak() {
+        let mut tracker = ReapTracker::new(ReapPolicy {
+            sustain_ops: 0,
+            ..ReapPolicy::default()
+        });
+        assert!(!tracker.on_free(0));
+    }
+}
@@ -0,0 +1,335 @@
+//! Slabbable two-phase retire / reclaim
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Wraps any Slabbable letting a slot be retired once it is logically finished
+/// while the kernel may still write into it e.g. until the CQE of a cancelled
+/// io_uring operation arrives. A retired slot is out of lookup but its memory
+/// and key stay held until reclaim() or advance_epoch() frees it.
+#[derive(Debug)]
+pub struct RetiringSlab<S> {
+    inner: S,
+    epoch: u64,
+    // epoch each retired slot was retired in
+    retired: HashMap<usize, u64>,
+    // retirements in epoch order, may hold slots reclaimed since
+    order: VecDeque<(u64, usize)>,
+}
+
+impl<S> RetiringSlab<S> {
+    /// Nothing retired in the given slab
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            epoch: 0,
+            retired: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    /// The current epoch
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+    /// Slots retired and not yet reclaimed
+    pub fn retired(&self) -> usize {
+        self.retired.len()
+    }
+    /// Whether the slot is retired and not yet reclaimed
+    pub fn is_retired(&self, slot: usize) -> bool {
+        self.retired.contains_key(&slot)
+    }
+    /// Take the taken slot out of lookup keeping its memory and key held
+    pub fn retire<T>(&mut self, slot: usize) -> Result<(), SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        if self.is_retired(slot) || self.inner.slot_get_ref(slot)?.is_none() {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        self.retired.insert(slot, self.epoch);
+        self.order.push_back((self.epoch, slot));
+        Ok(())
+    }
+    /// Free the retired slot once the kernel is known to be done with it
+    pub fn reclaim<T>(&mut self, slot: usize) -> Result<T, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+    {
+        if !self.is_retired(slot) {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        let item = self.inner.mark_for_reuse(slot)?;
+        self.retired.remove(&slot);
+        // drop the stale retirements once they outnumber the live ones
+        if self.order.len() > 2 * self.retired.len() {
+            let retired = &self.retired;
+            self.order
+                .retain(|(epoch, slot)| retired.get(slot) == Some(epoch));
+        }
+        Ok(item)
+    }
+    /// Start the next epoch freeing the slots retired before the previous
+    /// advance so that a full epoch passes between retire and reclaim. The
+    /// reclaimed items are handed to the closure. Returns the slots reclaimed.
+    pub fn advance_epoch<T, F>(&mut self, mut reclaimed: F) -> Result<usize, SlabbableError>
+    where
+        S: Slabbable<S, T, Error = SlabbableError>,
+        F: FnMut(usize, T),
+    {
+        self.epoch += 1;
+        let mut count = 0;
+        while let Some((epoch, slot)) = self.order.front().copied() {
+            if epoch + 2 > self.epoch {
+                break;
+            }
+            self.order.pop_front();
+            // reclaimed already or retired again since
+            if self.retired.get(&slot) != Some(&epoch) {
+                continue;
+            }
+            match self.reclaim(slot) {
+                Ok(item) => reclaimed(slot, item),
+                Err(e) => {
+                    self.order.push_front((epoch, slot));
+                    return Err(e);
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<S, T> Slabbable<RetiringSlab<S>, T> for RetiringSlab<S>
+where
+    S: Slabbable<S, T, Error = SlabbableError>,
+{
+    type Error = SlabbableError;
+    /// See trait
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(S::with_fixed_capacity(cap)?))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self.inner.reserve_next()
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_reserved_with(slot, with)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self.inner.cancel_reserved(slot)
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_next_with(with)
+    }
+    /// See trait - retired slots are freed through reclaim() only.
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        if self.is_retired(slot) {
+            return Err(SlabbableError::InvalidIndex(slot));
+        }
+        self.inner.mark_for_reuse(slot)
+    }
+    /// See trait - None for retired slots.
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        if self.is_retired(slot) {
+            return Ok(None);
+        }
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait - None for retired slots.
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        if self.is_retired(slot) {
+            return Ok(None);
+        }
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait - the retired slots are held.
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait - retired slots never move.
+    fn compact<M, R>(&mut self, mut movable: M, relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        let retired = &self.retired;
+        self.inner.compact(
+            |slot, item| !retired.contains_key(&slot) && movable(slot, item),
+            relocated,
+        )
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+
+    #[test]
+    fn retire_then_reclaim() {
+        let mut slab = RetiringSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        let a = slab.take_next_with(1).unwrap();
+        slab.take_next_with(2).unwrap();
+        slab.retire(a).unwrap();
+
+        // out of lookup but still held
+        assert_eq!(slab.slot_get_ref(a).unwrap(), None);
+        assert_eq!(slab.mark_for_reuse(a), Err(SlabbableError::InvalidIndex(a)));
+        assert_eq!(slab.retire(a), Err(SlabbableError::InvalidIndex(a)));
+        assert_eq!(slab.take_next_with(3), Err(SlabbableError::AtCapacity(2)));
+
+        assert_eq!(slab.reclaim(a).unwrap(), 1);
+        assert_eq!(slab.reclaim(a), Err(SlabbableError::InvalidIndex(a)));
+        assert_eq!(slab.retired(), 0);
+        slab.take_next_with(3).unwrap();
+    }
+
+    #[test]
+    fn advance_epoch_after_a_full_epoch() {
+        let mut slab = RetiringSlab::new(TestableSlab::<u8>::with_fixed_capacity(4).unwrap());
+        let keys: Vec<usize> = (0..3).map(|i| slab.take_next_with(i).unwrap()).collect();
+        slab.retire(keys[0]).unwrap();
+        slab.retire(keys[1]).unwrap();
+        let mut freed = vec![];
+        assert_eq!(
+            slab.advance_epoch(|key, item| freed.push((key, item))),
+            Ok(0)
+        );
+
+        slab.retire(keys[2]).unwrap();
+        assert_eq!(slab.reclaim(keys[1]).unwrap(), 1);
+        assert_eq!(
+            slab.advance_epoch(|key, item| freed.push((key, item))),
+            Ok(1)
+        );
+        assert_eq!(freed, vec![(keys[0], 0)]);
+        assert!(slab.is_retired(keys[2]));
+
+        assert_eq!(
+            slab.advance_epoch(|key, item| freed.push((key, item))),
+            Ok(1)
+        );
+        assert_eq!(slab.epoch(), 3);
+        assert_eq!(slab.retired(), 0);
+        assert_eq!(slab.remaining(), Some(4));
+    }
+
+    #[test]
+    fn reclaimed_retirements_are_pruned() {
+        let mut slab = RetiringSlab::new(TestableSlab::<u8>::with_fixed_capacity(4).unwrap());
+        let held = slab.take_next_with(0).unwrap();
+        slab.retire(held).unwrap();
+        for i in 1..100 {
+            let key = slab.take_next_with(i).unwrap();
+            slab.retire(key).unwrap();
+            assert_eq!(slab.reclaim(key).unwrap(), i);
+        }
+        assert!(slab.order.len() <= 2);
+        assert_eq!(slab.advance_epoch(|_, _| {}), Ok(0));
+        assert_eq!(slab.advance_epoch(|_, _| {}), Ok(1));
+        assert!(slab.order.is_empty());
+    }
+
+    #[test]
+    fn failed_reclaim_stays_retired() {
+        let mut slab = RetiringSlab::new(TestableSlab::<u8>::with_fixed_capacity(2).unwrap());
+        let a = slab.take_next_with(1).unwrap();
+        slab.retire(a).unwrap();
+        // the inner slab refuses to free the slot
+        slab.inner.mark_for_reuse(a).unwrap();
+
+        assert_eq!(slab.reclaim(a), Err(SlabbableError::InvalidIndex(a)));
+        assert!(slab.is_retired(a));
+        slab.advance_epoch(|_, _| {}).unwrap();
+        assert_eq!(
+            slab.advance_epoch(|_, _| {}),
+            Err(SlabbableError::InvalidIndex(a))
+        );
+        assert!(slab.is_retired(a));
+        assert_eq!(slab.order.len(), 1);
+    }
+}
@@ -0,0 +1,119 @@
+//! Slabbable runtime statistics
+
+/// Per slab counters maintained by the implementations with the `stats` feature.
+/// Plain integers as the slab lives within one thread.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlabStats {
+    /// Slots taken either directly or from a reservation
+    pub takes: u64,
+    /// Slots marked for re-use
+    pub frees: u64,
+    /// Slots currently taken
+    pub live: usize,
+    /// Slots currently reserved but not yet taken
+    pub reserved: usize,
+    /// Highest live + reserved since creation or the last reset
+    pub high_watermark: usize,
+    /// Reservations or takes rejected with AtCapacity
+    pub at_capacity: u64,
+    /// Times the sequential ID wrapped around at usize::MAX
+    pub revolutions: usize,
+}
+
+impl SlabStats {
+    #[inline]
+    fn _watermark(&mut self) {
+        let occupied = self.live + self.reserved;
+        if occupied > self.high_watermark {
+            self.high_watermark = occupied;
+        }
+    }
+    /// Record a slot reserved
+    #[inline]
+    pub fn on_reserve(&mut self) {
+        self.reserved += 1;
+        self._watermark();
+    }
+    /// Record a reserved slot taken
+    #[inline]
+    pub fn on_take_reserved(&mut self) {
+        self.reserved -= 1;
+        self.live += 1;
+        self.takes += 1;
+    }
+    /// Record a reserved slot released without taking it
+    #[inline]
+    pub fn on_cancel_reserved(&mut self) {
+        self.reserved -= 1;
+    }
+    /// Record a slot taken without reservation
+    #[inline]
+    pub fn on_take(&mut self) {
+        self.live += 1;
+        self.takes += 1;
+        self._watermark();
+    }
+    /// Record a slot marked for re-use
+    #[inline]
+    pub fn on_free(&mut self) {
+        self.live -= 1;
+        self.frees += 1;
+    }
+    /// Record a rejection with AtCapacity
+    #[inline]
+    pub fn on_at_capacity(&mut self) {
+        self.at_capacity += 1;
+    }
+    /// Record the sequential ID wrapping around
+    #[inline]
+    pub fn on_revolution(&mut self) {
+        self.revolutions += 1;
+    }
+    /// Zero the counters and restart the high-watermark from the current
+    /// occupancy. Live and reserved are kept as they reflect the slab.
+    pub fn reset(&mut self) {
+        *self = Self {
+            live: self.live,
+            reserved: self.reserved,
+            high_watermark: self.live + self.reserved,
+            ..Self::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counters_and_reset() {
+        let mut stats = SlabStats::default();
+        stats.on_reserve();
+        stats.on_take();
+        stats.on_take_reserved();
+        stats.on_take();
+        stats.on_free();
+        stats.on_at_capacity();
+        assert_eq!(
+            stats,
+            SlabStats {
+                takes: 3,
+                frees: 1,
+                live: 2,
+                reserved: 0,
+                high_watermark: 3,
+                at_capacity: 1,
+                revolutions: 0,
+            }
+        );
+        stats.reset();
+        assert_eq!(
+            stats,
+            SlabStats {
+                live: 2,
+                high_watermark: 2,
+                ..SlabStats::default()
+            }
+        );
+    }
+}
@@ -1,7 +1,9 @@
 //! Dummy Slabbable impl for testing.
 //! This isn't a performance implementation but simple enough to quickly test / showcase the trait.
 
-use super::{ReservedSlot, Slabbable, SlabbableError};
+use super::{
+    Quarantine, QuarantineQueue, ReservedSlot, ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
 
 #[derive(Debug)]
 pub struct TestableSlab<Item> {
@@ -16,12 +18,20 @@ pub struct TestableSlab<Item> {
     cur: usize,
     // (wrapping) Revolution
     rev: usize,
+    // Highest ID issued before cur wraps
+    last_id: usize,
+    // Freed slots stay counted as occupied until the quarantine is served
+    quarantine: Option<QuarantineQueue>,
 }
 
 impl<Item> TestableSlab<Item> {
+    /// Issue the IDs onwards from the given one e.g. to test near the wrap-around
+    pub fn skip_ids_to(&mut self, cur: usize) {
+        self.cur = cur;
+    }
     fn _take_next_cur(&mut self) -> usize {
         let spot = self.cur;
-        if self.cur == usize::MAX {
+        if self.cur >= self.last_id {
             self.cur = 0;
             self.rev = match self.rev {
                 usize::MAX => 0,
@@ -32,13 +42,16 @@ impl<Item> TestableSlab<Item> {
         }
         spot
     }
-    fn _find(&self, slot: usize) -> Option<usize> {
-        for x in 0..self.inner.capacity() {
-            if self.idx[x] == Some(slot) {
-                return Some(x);
+    fn _on_op(&mut self) {
+        if let Some(queue) = self.quarantine.as_mut() {
+            queue.on_op();
+            while queue.pop_due().is_some() {
+                self.occupied -= 1;
             }
         }
-        return None;
+    }
+    fn _find(&self, slot: usize) -> Option<usize> {
+        (0..self.inner.capacity()).find(|&x| self.idx[x] == Some(slot))
     }
 }
 
@@ -56,10 +69,13 @@ where
             reserved: vec![],
             cur: 0,
             rev: 0,
+            last_id: usize::MAX,
+            quarantine: None,
         })
     }
     /// See trait
     fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        self._on_op();
         if self.occupied == self.inner.capacity() {
             return Err(SlabbableError::AtCapacity(self.inner.capacity()));
         }
@@ -69,52 +85,62 @@ where
         Ok(ReservedSlot::issue(slot))
     }
     fn take_reserved_with(&mut self, slot: ReservedSlot, with: Item) -> Result<usize, Self::Error> {
-        let mut found: Option<usize> = None;
-        for ri in 0..self.reserved.len() {
-            if self.reserved[ri] == slot.id() {
-                found = Some(slot.id());
-                break;
+        self._on_op();
+        let found = match self.reserved.iter().position(|r| *r == slot.id()) {
+            Some(ri) => self.reserved.swap_remove(ri),
+            None => return Err(SlabbableError::InvalidIndex(slot.id())),
+        };
+        // occupied was already counted upon reserve
+        for x in 0..self.inner.capacity() {
+            if self.inner[x].is_none() {
+                self.inner[x] = Some(with);
+                self.idx[x] = Some(found);
+                return Ok(found);
             }
         }
-
-        if found.is_none() {
-            return Err(SlabbableError::InvalidIndex(slot.id()));
-        }
-        if let Some(found) = found {
-            for x in 0..self.inner.capacity() {
-                if self.inner[x].is_none() {
-                    self.occupied += 1;
-                    self.inner[x] = Some(with);
-                    self.idx[x] = Some(found);
-                    return Ok(x);
-                }
+        Err(SlabbableError::Bug("Capacity check"))
+    }
+    /// See trait
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error> {
+        self._on_op();
+        match self.reserved.iter().position(|r| *r == slot.id()) {
+            Some(ri) => {
+                self.reserved.swap_remove(ri);
+                self.occupied -= 1;
+                Ok(())
             }
+            None => Err(SlabbableError::InvalidIndex(slot.id())),
         }
-        Err(SlabbableError::Bug("Capacity check"))
     }
     /// See trait - Let's just do simple non-optimised linear search for testing
     fn take_next_with(&mut self, with: Item) -> Result<usize, Self::Error> {
+        self._on_op();
         if self.occupied == self.inner.capacity() {
             return Err(SlabbableError::AtCapacity(self.inner.capacity()));
         }
         for x in 0..self.inner.capacity() {
             if self.inner[x].is_none() {
+                let slot = self._take_next_cur();
                 self.occupied += 1;
                 self.inner[x] = Some(with);
-                self.idx[x] = Some(self._take_next_cur());
-                return Ok(x);
+                self.idx[x] = Some(slot);
+                return Ok(slot);
             }
         }
         Err(SlabbableError::Bug("Capacity check"))
     }
     /// See trait    
     fn mark_for_reuse(&mut self, slot: usize) -> Result<Item, Self::Error> {
+        self._on_op();
         if let Some(x) = self._find(slot) {
             let ret = match &self.inner[x] {
                 Some(item) => item.clone(),
                 None => return Err(SlabbableError::Bug("Find-check is buggy")),
             };
-            self.occupied -= 1;
+            match self.quarantine.as_mut() {
+                Some(queue) => queue.on_free(x),
+                None => self.occupied -= 1,
+            }
             self.inner[x] = None;
             self.idx[x] = None;
             return Ok(ret);
@@ -136,7 +162,7 @@ where
     fn slot_get_ref(&self, slot: usize) -> Result<Option<&Item>, Self::Error> {
         if let Some(x) = self._find(slot) {
             if let Some(itm) = &self.inner[x] {
-                return Ok(Some(&itm));
+                return Ok(Some(itm));
             } else {
                 return Err(SlabbableError::Bug("Get ref."));
             }
@@ -155,6 +181,63 @@ where
             1_usize.. => Some(rem),
         }
     }
+    /// See trait - the sequential IDs wrap around below the limit.
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        let Some(last_id) = limit.checked_sub(1) else {
+            return false;
+        };
+        self.last_id = last_id;
+        if self.cur > last_id {
+            self.cur = last_id;
+            self._take_next_cur();
+        }
+        true
+    }
+    /// See trait - the IDs are never re-used so only the capacity is held.
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        match (quarantine, self.quarantine.as_mut()) {
+            (Some(quarantine), Some(queue)) => queue.set_quarantine(quarantine),
+            (Some(quarantine), None) => self.quarantine = Some(QuarantineQueue::new(quarantine)),
+            (None, _) => {
+                if let Some(mut queue) = self.quarantine.take() {
+                    while queue.pop_any().is_some() {
+                        self.occupied -= 1;
+                    }
+                }
+            }
+        }
+    }
+    /// See trait - taken items are packed to the front.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error> {
+        if self.occupied > 0 && mode == ResizeMode::IfEmpty {
+            return Err(SlabbableError::NotEmpty(self.occupied));
+        }
+        if self.occupied > new_cap {
+            return Err(SlabbableError::BelowOccupied(self.occupied));
+        }
+        let mut inner = vec![None; new_cap];
+        let mut idx = vec![None; new_cap];
+        let taken = self.inner.drain(..).zip(self.idx.drain(..));
+        for (x, (item, slot)) in taken.filter(|(item, _)| item.is_some()).enumerate() {
+            inner[x] = item;
+            idx[x] = slot;
+        }
+        self.inner = inner;
+        self.idx = idx;
+        Ok(())
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        let cap = self.inner.capacity();
+        let allocated = cap * size_of::<Option<Item>>()
+            + self.idx.capacity() * size_of::<Option<usize>>()
+            + self.reserved.capacity() * size_of::<usize>();
+        SlabMemory::from_slots(allocated, cap, self.occupied, size_of::<Item>())
+    }
     /// See trait    
     fn reap(&mut self) -> Option<usize> {
         // We don't support it
@@ -0,0 +1,334 @@
+//! Slabbable occupancy watermarks
+
+use crate::{
+    Placement, Quarantine, ReapBudget, ReapPolicy, ReapProgress, Relocation, ReservedSlot,
+    ResizeMode, SlabMemory, Slabbable, SlabbableError,
+};
+
+#[cfg(feature = "stats")]
+use crate::SlabStats;
+
+/// Occupancy percents of the capacity where backpressure starts and stops.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watermarks {
+    /// Backpressure starts once occupancy reaches this percent
+    pub high_percent: usize,
+    /// Backpressure stops once occupancy falls to this percent
+    pub low_percent: usize,
+}
+
+impl Default for Watermarks {
+    fn default() -> Self {
+        Self {
+            high_percent: 90,
+            low_percent: 70,
+        }
+    }
+}
+
+/// Whether the slab is accepting or pushing back
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PressureState {
+    /// Occupancy is below the high watermark or has fallen to the low one
+    Accepting,
+    /// Occupancy has reached the high watermark and not yet fallen to the low one
+    Backpressure,
+}
+
+/// Called upon each transition with the state entered
+pub type PressureCallback = Box<dyn FnMut(PressureState)>;
+
+/// Wraps any Slabbable evaluating the Watermarks upon reserve, take, cancel and
+/// mark_for_reuse so servers get edge-triggered backpressure without polling
+/// remaining(). Reserved slots count as occupied.
+pub struct WatermarkSlab<S> {
+    inner: S,
+    watermarks: Watermarks,
+    high_slots: usize,
+    low_slots: usize,
+    state: PressureState,
+    // transition not yet taken through take_transition()
+    transition: Option<PressureState>,
+    callback: Option<PressureCallback>,
+}
+
+impl<S> core::fmt::Debug for WatermarkSlab<S>
+where
+    S: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WatermarkSlab")
+            .field("inner", &self.inner)
+            .field("watermarks", &self.watermarks)
+            .field("high_slots", &self.high_slots)
+            .field("low_slots", &self.low_slots)
+            .field("state", &self.state)
+            .field("transition", &self.transition)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl<S> WatermarkSlab<S> {
+    /// Watch the occupancy of the given slab against the watermarks
+    pub fn new<T>(inner: S, watermarks: Watermarks) -> Self
+    where
+        S: Slabbable<S, T>,
+    {
+        let mut slab = Self {
+            inner,
+            watermarks,
+            high_slots: 0,
+            low_slots: 0,
+            state: PressureState::Accepting,
+            transition: None,
+            callback: None,
+        };
+        slab.set_watermarks(watermarks);
+        slab._evaluate();
+        slab.transition = None;
+        slab
+    }
+    /// Change the watermarks, evaluated upon the next operation
+    pub fn set_watermarks<T>(&mut self, watermarks: Watermarks)
+    where
+        S: Slabbable<S, T>,
+    {
+        self.watermarks = watermarks;
+        let cap = self.inner.capacity();
+        // at least one slot in between so the state can't flap
+        self.high_slots = (cap * watermarks.high_percent).div_ceil(100).max(1);
+        self.low_slots = (cap * watermarks.low_percent / 100).min(self.high_slots - 1);
+    }
+    /// Call the given callback upon each transition
+    pub fn set_callback(&mut self, callback: Option<PressureCallback>) {
+        self.callback = callback;
+    }
+    /// The current state
+    pub fn pressure_state(&self) -> PressureState {
+        self.state
+    }
+    /// The state entered since the previous call if it changed
+    pub fn take_transition(&mut self) -> Option<PressureState> {
+        self.transition.take()
+    }
+    /// The wrapped slab
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+    fn _occupied<T>(&self) -> usize
+    where
+        S: Slabbable<S, T>,
+    {
+        self.inner.capacity() - self.inner.remaining().unwrap_or(0)
+    }
+    fn _evaluate<T>(&mut self)
+    where
+        S: Slabbable<S, T>,
+    {
+        let occupied = self._occupied();
+        let next = match self.state {
+            PressureState::Accepting if occupied >= self.high_slots => PressureState::Backpressure,
+            PressureState::Backpressure if occupied <= self.low_slots => PressureState::Accepting,
+            _ => return,
+        };
+        self.state = next;
+        self.transition = Some(next);
+        if let Some(callback) = self.callback.as_mut() {
+            callback(next);
+        }
+    }
+}
+
+impl<S, T> Slabbable<WatermarkSlab<S>, T> for WatermarkSlab<S>
+where
+    S: Slabbable<S, T>,
+{
+    type Error = S::Error;
+    /// See trait - with the default Watermarks
+    fn with_fixed_capacity(cap: usize) -> Result<Self, Self::Error> {
+        Ok(Self::new(
+            S::with_fixed_capacity(cap)?,
+            Watermarks::default(),
+        ))
+    }
+    /// See trait
+    #[inline]
+    fn reserve_next(&mut self) -> Result<ReservedSlot, Self::Error> {
+        let slot = self.inner.reserve_next()?;
+        self._evaluate();
+        Ok(slot)
+    }
+    /// See trait
+    #[inline]
+    fn take_reserved_with(&mut self, slot: ReservedSlot, with: T) -> Result<usize, Self::Error> {
+        self.inner.take_reserved_with(slot, with)
+    }
+    /// See trait
+    #[inline]
+    fn cancel_reserved(&mut self, slot: ReservedSlot) -> Result<(), Self::Error>
+    where
+        Self::Error: From<SlabbableError>,
+    {
+        self.inner.cancel_reserved(slot)?;
+        self._evaluate();
+        Ok(())
+    }
+    /// See trait
+    #[inline]
+    fn take_next_with(&mut self, with: T) -> Result<usize, Self::Error> {
+        let slot = self.inner.take_next_with(with)?;
+        self._evaluate();
+        Ok(slot)
+    }
+    /// See trait
+    #[inline]
+    fn mark_for_reuse(&mut self, slot: usize) -> Result<T, Self::Error> {
+        let item = self.inner.mark_for_reuse(slot)?;
+        self._evaluate();
+        Ok(item)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_mut(&mut self, slot: usize) -> Result<Option<&mut T>, Self::Error> {
+        self.inner.slot_get_mut(slot)
+    }
+    /// See trait
+    #[inline]
+    fn slot_get_ref(&self, slot: usize) -> Result<Option<&T>, Self::Error> {
+        self.inner.slot_get_ref(slot)
+    }
+    /// See trait
+    #[inline]
+    fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+    /// See trait
+    #[inline]
+    fn remaining(&self) -> Option<usize> {
+        self.inner.remaining()
+    }
+    /// See trait - the watermarks are kept as percents of the new capacity.
+    fn try_resize_fixed_capacity(
+        &mut self,
+        new_cap: usize,
+        mode: ResizeMode,
+    ) -> Result<(), Self::Error>
+    where
+        Self::Error: From<SlabbableError>,
+    {
+        self.inner.try_resize_fixed_capacity(new_cap, mode)?;
+        self.set_watermarks(self.watermarks);
+        self._evaluate();
+        Ok(())
+    }
+    /// See trait
+    fn set_quarantine(&mut self, quarantine: Option<Quarantine>) {
+        self.inner.set_quarantine(quarantine)
+    }
+    /// See trait
+    fn placement(&self) -> Option<Placement> {
+        self.inner.placement()
+    }
+    /// See trait
+    fn set_placement(&mut self, placement: Placement) -> bool {
+        self.inner.set_placement(placement)
+    }
+    /// See trait
+    fn set_key_limit(&mut self, limit: usize) -> bool {
+        self.inner.set_key_limit(limit)
+    }
+    /// See trait
+    fn compact<M, R>(&mut self, movable: M, relocated: R) -> Option<usize>
+    where
+        M: FnMut(usize, &T) -> bool,
+        R: FnMut(Relocation, &T),
+    {
+        self.inner.compact(movable, relocated)
+    }
+    /// See trait
+    fn memory_usage(&self) -> SlabMemory {
+        self.inner.memory_usage()
+    }
+    /// See trait
+    fn reap(&mut self) -> Option<usize> {
+        self.inner.reap()
+    }
+    /// See trait
+    fn reap_bounded(&mut self, budget: ReapBudget) -> ReapProgress {
+        self.inner.reap_bounded(budget)
+    }
+    /// See trait
+    fn set_reap_policy(&mut self, policy: Option<ReapPolicy>) {
+        self.inner.set_reap_policy(policy)
+    }
+    /// See trait
+    fn reap_due(&self) -> bool {
+        self.inner.reap_due()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn stats(&self) -> Option<SlabStats> {
+        self.inner.stats()
+    }
+    /// See trait
+    #[cfg(feature = "stats")]
+    fn reset_stats(&mut self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod test {
+    use super::*;
+    use crate::testable::TestableSlab;
+    use core::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn edge_triggered_with_hysteresis() {
+        let mut slab = WatermarkSlab::new(
+            TestableSlab::<u8>::with_fixed_capacity(10).unwrap(),
+            Watermarks::default(),
+        );
+        let seen = Rc::new(RefCell::new(vec![]));
+        let seen_cb = seen.clone();
+        slab.set_callback(Some(Box::new(move |state| {
+            seen_cb.borrow_mut().push(state)
+        })));
+
+        let mut slots: Vec<usize> = (0..8).map(|i| slab.take_next_with(i).unwrap()).collect();
+        assert_eq!(slab.pressure_state(), PressureState::Accepting);
+        let reserved = slab.reserve_next().unwrap();
+        assert_eq!(slab.pressure_state(), PressureState::Backpressure);
+        assert_eq!(slab.take_transition(), Some(PressureState::Backpressure));
+        assert_eq!(slab.take_transition(), None);
+
+        // 8 of 10 is between the watermarks
+        slab.cancel_reserved(reserved).unwrap();
+        assert_eq!(slab.pressure_state(), PressureState::Backpressure);
+        slab.mark_for_reuse(slots.pop().unwrap()).unwrap();
+        assert_eq!(slab.pressure_state(), PressureState::Accepting);
+        assert_eq!(slab.take_transition(), Some(PressureState::Accepting));
+
+        slab.take_next_with(8).unwrap();
+        assert_eq!(slab.pressure_state(), PressureState::Accepting);
+        assert_eq!(
+            *seen.borrow(),
+            vec![PressureState::Backpressure, PressureState::Accepting]
+        );
+    }
+
+    #[test]
+    fn watermarks_keep_a_gap() {
+        let slab = WatermarkSlab::new(
+            TestableSlab::<u8>::with_fixed_capacity(1).unwrap(),
+            Watermarks {
+                high_percent: 50,
+                low_percent: 50,
+            },
+        );
+        assert_eq!((slab.high_slots, slab.low_slots), (1, 0));
+    }
+}